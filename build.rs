@@ -1,13 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::error::Error;
 use std::fs;
-
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
 use std::time::SystemTime;
 
-use serde::Serialize;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[path = "src/text.rs"]
+mod text;
+#[path = "src/ansi.rs"]
+mod ansi;
+#[path = "src/markdown.rs"]
+mod markdown;
+#[path = "src/search/tokenize.rs"]
+mod tokenize;
+#[path = "src/wrap.rs"]
+mod wrap;
 
 fn main() {
   let out_dir = env::var_os("OUT_DIR").unwrap();
@@ -16,31 +30,424 @@ fn main() {
   let root_path = Path::new(&cargo_dir).join("root");
   let index_path = Path::new(&cargo_dir).join("www/index.html");
   let sitemap_path = Path::new(&cargo_dir).join("www/sitemap.xml");
+  let atom_path = Path::new(&cargo_dir).join("www/feed.xml");
+  let rss_path = Path::new(&cargo_dir).join("www/rss.xml");
+  // any rerun-if line replaces cargo's default of rerunning on every package change, so all inputs are listed
+  let inputs = [
+    "build.rs",
+    "root",
+    "www/index.html",
+    "src/text.rs",
+    "src/ansi.rs",
+    "src/markdown.rs",
+    "src/wrap.rs",
+    "src/search/tokenize.rs",
+  ];
+  for input in inputs {
+    println!("cargo:rerun-if-changed={}", input);
+  }
 
+  let root_metadata = root_path.metadata().unwrap();
+  // rendered posts of earlier builds may be gone or not markdown anymore
+  let _ = fs::remove_dir_all(Path::new(&cargo_dir).join(RENDERED_URL));
+  let dates = git_dates(root_path.as_path());
+  // without history the root is as old as the build
+  let built = source_date_epoch().unwrap_or_else(|| {
+    SystemTime::now()
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+  });
+  let (modified, created) = entry_dates(&dates, "", built);
   let mut root: Entry = Entry {
     filename: Box::new("".to_string()),
     url: Box::new("".to_string()),
-    size: root_path.metadata().unwrap().len(),
-    modified: SystemTime::now()
-      .duration_since(SystemTime::UNIX_EPOCH)
-      .unwrap()
-      .as_secs(),
+    size: root_metadata.len(),
+    mode: root_metadata.mode() & 0o7777,
+    nlink: root_metadata.nlink(),
+    owner: id_name(root_metadata.uid()),
+    group: id_name(root_metadata.gid()),
+    modified,
+    created,
     is_dir: true,
+    rendered: false,
+    link: None,
+    sha256: None,
+    meta: None,
     entries: HashMap::new(),
   };
-  visit_dirs(&mut root, root_path.as_path(), "").expect("couldn't read dir");
-  create_dirs(&root, &index_path);
+  visit_dirs(&mut root, root_path.as_path(), root_path.as_path(), "", &dates).expect("couldn't read dir");
+  println!("cargo:rustc-env=SITE_HOME={}", site_home(&root));
+  create_dirs(&root, root_path.as_path(), &index_path);
   generate_sitemap(&root, &sitemap_path).expect("couldn't generate sitemap");
-  let root_serialized: String = ron::ser::to_string(&root).unwrap();
+  generate_feeds(&root, root_path.as_path(), &atom_path, &rss_path).expect("couldn't generate feeds");
+  // chunked builds only know the root, everything below is listed by the manifests
+  let manifests = manifests_enabled();
+  let static_root = if manifests {
+    let manifest_path = Path::new(&cargo_dir).join(MANIFEST_URL);
+    write_manifests(&root, &manifest_path).expect("couldn't write manifests");
+    Entry {
+      entries: HashMap::new(),
+      ..root.clone()
+    }
+  } else {
+    root.clone()
+  };
   let out = format!(
-    "pub const ROOT_SERIALIZED: &str = \"{}\";\n",
-    root_serialized.replace("\"", "\\\"")
+    "pub static ROOT: Entry = {};\npub const UNION_MOUNTS: &[(&str, &str)] = &{:?};\npub const MANIFESTS: bool = {};\n",
+    entry_literal(&static_root, root_path.as_path(), inline_limit()),
+    UNION_MOUNTS,
+    manifests
   );
   fs::write(&dest_path, out).unwrap();
+  let index = search_index(&root, root_path.as_path());
+  // chunked builds fetch the index like the manifests, so new posts can be found without a rebuild
+  let index_literal = if manifests {
+    let index_path = Path::new(&cargo_dir).join(MANIFEST_URL).join(SEARCH_INDEX_NAME);
+    fs::write(&index_path, ron::ser::to_string(&index).unwrap()).unwrap();
+    "Index { docs: &[], terms: &[] }".to_string()
+  } else {
+    index_literal(&index)
+  };
+  let search_path = Path::new(&out_dir).join("search.rs");
+  fs::write(&search_path, format!("pub static INDEX: Index = {};\n", index_literal)).unwrap();
 }
 
 const PARENT_URL: &str = "dirs/";
+const CHARSET_META: &str = "<meta charset=\"UTF-8\" />";
 const BASE_URL: &str = "https://www.gfelber.dev";
+const SITE_NAME: &str = "gfelber.dev";
+const SITE_OWNER: &str = "gfelber";
+// directories whose content is left out of the sitemap and the feeds
+const SKIPPED_DIRS: &[&str] = &["res", "img"];
+// newest posts in the feeds, SITE_FEED_LIMIT overrides it
+const FEED_LIMIT: usize = 20;
+// (target, source): entries of source also appear in target, unless target has one with that name
+const UNION_MOUNTS: &[(&str, &str)] = &[("/", "/old")];
+
+// per directory listings, served next to the content under /root
+const MANIFEST_URL: &str = "manifests/";
+const MANIFEST_NAME: &str = ".manifest.ron";
+// search index of chunked builds, next to the manifest of the root
+const SEARCH_INDEX_NAME: &str = ".search.ron";
+
+// SITE_MANIFESTS=1 leaves the tree out of the binary, new content then ships without rebuilding it
+fn manifests_enabled() -> bool {
+  println!("cargo:rerun-if-env-changed=SITE_MANIFESTS");
+  env::var("SITE_MANIFESTS").is_ok_and(|manifests| !manifests.is_empty() && manifests != "0")
+}
+
+// directory ~ and cd without arguments lead to, SITE_HOME overrides it
+const HOME: &str = "/posts";
+
+// falls back to the root if the site has no HOME, a SITE_HOME that isn't a directory fails the build
+fn site_home(root: &Entry) -> String {
+  println!("cargo:rerun-if-env-changed=SITE_HOME");
+  let configured = env::var("SITE_HOME").ok();
+  let home = configured.as_deref().unwrap_or(HOME);
+  let dir = home
+    .split('/')
+    .filter(|name| !name.is_empty())
+    .try_fold(root, |entry, name| entry.entries.values().find(|child| child.filename.as_str() == name));
+  match dir {
+    Some(dir) if dir.is_dir => format!("/{}", dir.url),
+    _ if configured.is_none() => "/".to_string(),
+    _ => panic!("SITE_HOME {} isn't a directory of the site", home),
+  }
+}
+
+// SITE_DRAFTS=1 keeps posts marked as draft in the tree and the sitemap
+fn drafts_enabled() -> bool {
+  static DRAFTS: OnceLock<bool> = OnceLock::new();
+  *DRAFTS.get_or_init(|| {
+    println!("cargo:rerun-if-env-changed=SITE_DRAFTS");
+    env::var("SITE_DRAFTS").is_ok_and(|drafts| !drafts.is_empty() && drafts != "0")
+  })
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FrontMatter {
+  title: Option<String>,
+  date: Option<String>,
+  tags: Vec<String>,
+  summary: Option<String>,
+  draft: bool,
+  image: Option<String>,
+}
+
+// front matter of a markdown file, None if it has none
+fn read_meta(path: &Path) -> Option<Meta> {
+  if path.extension().is_none_or(|extension| extension != "md") {
+    return None;
+  }
+  let content = fs::read_to_string(path).ok()?;
+  let (delimiter, front_matter, _body) = text::front_matter(&content)?;
+  let parsed = if front_matter.trim().is_empty() {
+    Ok(FrontMatter::default())
+  } else if delimiter == "+++" {
+    parse_toml(front_matter)
+  } else {
+    serde_yaml::from_str(front_matter).map_err(|error| error.to_string())
+  };
+  let front_matter = match parsed {
+    Ok(front_matter) => front_matter,
+    Err(error) => {
+      println!("cargo:warning={}: invalid front matter: {}", path.display(), error);
+      return None;
+    }
+  };
+  let date = front_matter.date.as_deref().and_then(|date| {
+    let timestamp = parse_date(date);
+    if timestamp.is_none() {
+      println!("cargo:warning={}: invalid date {}", path.display(), date);
+    }
+    timestamp
+  });
+  Some(Meta {
+    title: front_matter.title,
+    date,
+    tags: front_matter.tags,
+    summary: front_matter.summary,
+    draft: front_matter.draft,
+    image: front_matter.image,
+  })
+}
+
+fn parse_toml(front_matter: &str) -> Result<FrontMatter, String> {
+  let mut table: toml::Table = toml::from_str(front_matter).map_err(|error| error.to_string())?;
+  // bare TOML dates are no strings
+  if let Some(toml::Value::Datetime(date)) = table.get("date") {
+    let date = date.to_string();
+    table.insert("date".to_string(), toml::Value::String(date));
+  }
+  table.try_into().map_err(|error: toml::de::Error| error.to_string())
+}
+
+// RFC 3339 timestamps or dates with an optional time, as UTC
+fn parse_date(date: &str) -> Option<u64> {
+  let timestamp = DateTime::parse_from_rfc3339(date)
+    .map(|date| date.timestamp())
+    .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").map(|date| date.and_utc().timestamp()))
+    .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").map(|date| date.and_utc().timestamp()))
+    .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|date| date.and_time(Default::default()).and_utc().timestamp()))
+    .ok()?;
+  u64::try_from(timestamp).ok()
+}
+
+// direct entries of a directory as listed in its manifest
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+  filename: &'a str,
+  size: u64,
+  mode: u32,
+  nlink: u64,
+  owner: &'a str,
+  group: &'a str,
+  modified: u64,
+  created: u64,
+  is_dir: bool,
+  link: Option<&'a str>,
+  sha256: Option<&'a str>,
+  meta: Option<&'a Meta>,
+}
+
+fn write_manifests(entry: &Entry, manifest_path: &Path) -> Result<(), Box<dyn Error>> {
+  let dir = manifest_path.join(entry.url.as_str());
+  fs::create_dir_all(&dir)?;
+  let mut children: Vec<&Entry> = entry.entries.values().collect();
+  children.sort_by(|a, b| a.filename.cmp(&b.filename));
+  let listed: Vec<ManifestEntry> = children
+    .iter()
+    .map(|child| ManifestEntry {
+      filename: &child.filename,
+      size: child.size,
+      mode: child.mode,
+      nlink: child.nlink,
+      owner: &child.owner,
+      group: &child.group,
+      modified: child.modified,
+      created: child.created,
+      is_dir: child.is_dir,
+      link: child.link.as_deref(),
+      sha256: child.sha256.as_deref(),
+      meta: child.meta.as_ref(),
+    })
+    .collect();
+  fs::write(dir.join(MANIFEST_NAME), ron::ser::to_string(&listed)?)?;
+  for child in children.into_iter().filter(|child| child.is_dir) {
+    write_manifests(child, manifest_path)?;
+  }
+  Ok(())
+}
+
+// files up to this size are compiled into the binary, SITE_INLINE_KIB overrides it (0 disables)
+const INLINE_KIB: u64 = 4;
+
+fn inline_limit() -> u64 {
+  println!("cargo:rerun-if-env-changed=SITE_INLINE_KIB");
+  let kib = env::var("SITE_INLINE_KIB")
+    .ok()
+    .and_then(|kib| kib.parse().ok())
+    .unwrap_or(INLINE_KIB);
+  kib * 1024
+}
+
+// rust expression of the static tree, children are sorted by name for binary search
+fn entry_literal(entry: &Entry, root_path: &Path, inline_limit: u64) -> String {
+  let mut children: Vec<&Entry> = entry.entries.values().collect();
+  children.sort_by(|a, b| a.filename.cmp(&b.filename));
+  let entries: Vec<String> = children
+    .iter()
+    .map(|child| entry_literal(child, root_path, inline_limit))
+    .collect();
+  // small text files are compiled into the binary, binary assets stay fetched
+  let path = content_path(root_path, entry);
+  let content = if !entry.is_dir
+    && entry.link.is_none()
+    && entry.size <= inline_limit
+    && fs::read_to_string(&path).is_ok()
+  {
+    format!("Some(Content::Inline(include_str!({:?})))", path)
+  } else {
+    "None".to_string()
+  };
+  let meta = match &entry.meta {
+    Some(meta) => format!(
+      "Some(Meta {{ title: {:?}, date: {:?}, tags: &{:?}, summary: {:?}, draft: {} }})",
+      meta.title, meta.date, meta.tags, meta.summary, meta.draft
+    ),
+    None => "None".to_string(),
+  };
+  format!(
+    "Entry {{ filename: Cow::Borrowed({:?}), url: Cow::Borrowed({:?}), size: {}, mode: {:#o}, nlink: {}, owner: {:?}, group: {:?}, modified: {}, created: {}, is_dir: {}, link: {:?}, sha256: {:?}, meta: {}, provider: None, content: {}, entries: &[{}] }}",
+    entry.filename,
+    entry.url,
+    entry.size,
+    entry.mode,
+    entry.nlink,
+    entry.owner,
+    entry.group,
+    entry.modified,
+    entry.created,
+    entry.is_dir,
+    entry.link,
+    entry.sha256,
+    meta,
+    content,
+    entries.join(", ")
+  )
+}
+
+// inverted index of all text files for the search command
+#[derive(Serialize)]
+struct SearchIndex {
+  docs: Vec<SearchDoc>,
+  terms: Vec<SearchTerm>,
+}
+
+#[derive(Serialize)]
+struct SearchDoc {
+  url: String,
+  terms: u32,
+  // position of the first term of every line
+  lines: Vec<u32>,
+}
+
+// terms are sorted for binary search
+#[derive(Serialize)]
+struct SearchTerm {
+  term: String,
+  postings: Vec<SearchPosting>,
+}
+
+#[derive(Serialize)]
+struct SearchPosting {
+  doc: u32,
+  positions: Vec<u32>,
+}
+
+fn search_index(root: &Entry, root_path: &Path) -> SearchIndex {
+  let mut documents = Vec::new();
+  collect_documents(&mut documents, root, root_path);
+  documents.sort();
+  // term -> (document, positions), positions count the terms of a document
+  let mut terms: BTreeMap<String, Vec<SearchPosting>> = BTreeMap::new();
+  let mut docs = Vec::new();
+  for (doc, (url, content)) in documents.iter().enumerate() {
+    let doc = doc as u32;
+    let mut position = 0;
+    // position of the first term of every line, maps positions back to lines
+    let mut lines = Vec::new();
+    for line in content.lines() {
+      lines.push(position);
+      for term in tokenize::terms(line) {
+        let postings = terms.entry(term).or_default();
+        if postings.last().is_none_or(|posting| posting.doc != doc) {
+          postings.push(SearchPosting { doc, positions: vec![] });
+        }
+        if let Some(posting) = postings.last_mut() {
+          posting.positions.push(position);
+        }
+        position += 1;
+      }
+    }
+    docs.push(SearchDoc {
+      url: url.clone(),
+      terms: position,
+      lines,
+    });
+  }
+  SearchIndex {
+    docs,
+    terms: terms
+      .into_iter()
+      .map(|(term, postings)| SearchTerm { term, postings })
+      .collect(),
+  }
+}
+
+// rust expression of the index for builds that compile it in
+fn index_literal(index: &SearchIndex) -> String {
+  let docs: Vec<String> = index
+    .docs
+    .iter()
+    .map(|doc| format!("Doc {{ url: {:?}, terms: {}, lines: &{:?} }}", doc.url, doc.terms, doc.lines))
+    .collect();
+  let terms: Vec<String> = index
+    .terms
+    .iter()
+    .map(|term| {
+      let postings: Vec<String> = term
+        .postings
+        .iter()
+        .map(|posting| format!("Posting {{ doc: {}, positions: &{:?} }}", posting.doc, posting.positions))
+        .collect();
+      format!("Term {{ term: {:?}, postings: &[{}] }}", term.term, postings.join(", "))
+    })
+    .collect();
+  format!("Index {{ docs: &[{}], terms: &[{}] }}", docs.join(", "), terms.join(", "))
+}
+
+// (url, content) of the text files, links are indexed at their target
+// files visitors can't read are left out, their content would show up in the results
+fn collect_documents(documents: &mut Vec<(String, String)>, entry: &Entry, root_path: &Path) {
+  for child in entry.entries.values() {
+    if child.is_dir {
+      collect_documents(documents, child, root_path);
+    } else if child.link.is_none() && child.mode & 0o4 != 0 {
+      if let Ok(content) = fs::read_to_string(content_path(root_path, child)) {
+        // the front matter isn't shown, so line numbers start after it
+        let content = if child.meta.is_some() {
+          text::body(&content).to_string()
+        } else {
+          content
+        };
+        documents.push((child.url.to_string(), content));
+      }
+    }
+  }
+}
 
 fn generate_sitemap(root: &Entry, sitemap_path: &Path) -> Result<(), Box<dyn Error>> {
   let mut urls = Vec::new();
@@ -53,7 +460,7 @@ fn generate_sitemap(root: &Entry, sitemap_path: &Path) -> Result<(), Box<dyn Err
   ));
 
   // Collect all URLs from the root structure
-  collect_urls(&mut urls, root, "");
+  collect_urls(&mut urls, root, root, "");
 
   let sitemap = format!(
     "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}\n</urlset>",
@@ -64,21 +471,137 @@ fn generate_sitemap(root: &Entry, sitemap_path: &Path) -> Result<(), Box<dyn Err
   Ok(())
 }
 
+// anything inside one of SKIPPED_DIRS
+fn is_skipped(url: &str) -> bool {
+  let mut components: Vec<&str> = url.trim_matches('/').split('/').collect();
+  components.pop();
+  components.iter().any(|component| SKIPPED_DIRS.contains(component))
+}
+
+fn feed_limit() -> usize {
+  println!("cargo:rerun-if-env-changed=SITE_FEED_LIMIT");
+  env::var("SITE_FEED_LIMIT")
+    .ok()
+    .and_then(|limit| limit.parse().ok())
+    .unwrap_or(FEED_LIMIT)
+}
+
+// comma separated paths in SITE_FEED_EXCLUDE are left out of the feeds, e.g. "old,posts/notes"
+fn feed_excluded() -> Vec<String> {
+  println!("cargo:rerun-if-env-changed=SITE_FEED_EXCLUDE");
+  env::var("SITE_FEED_EXCLUDE")
+    .unwrap_or_default()
+    .split(',')
+    .map(|path| path.trim().trim_matches('/').to_string())
+    .filter(|path| !path.is_empty())
+    .collect()
+}
+
+// newest markdown files first, by their declared date
+fn collect_posts<'a>(posts: &mut Vec<&'a Entry>, entry: &'a Entry, excluded: &[String]) {
+  for child in entry.entries.values() {
+    let url = child.url.as_str();
+    if is_skipped(url) || child.link.is_some() {
+      continue;
+    }
+    if excluded.iter().any(|path| url == path || url.starts_with(&format!("{}/", path))) {
+      continue;
+    }
+    if child.is_dir {
+      collect_posts(posts, child, excluded);
+    } else if url.ends_with(".md") {
+      posts.push(child);
+    }
+  }
+}
+
+fn post_date(entry: &Entry) -> u64 {
+  entry.meta.as_ref().and_then(|meta| meta.date).unwrap_or(entry.modified)
+}
+
+fn post_title(entry: &Entry) -> &str {
+  entry.meta.as_ref().and_then(|meta| meta.title.as_deref()).unwrap_or(&entry.filename)
+}
+
+// summary from the front matter, the start of the text otherwise
+fn post_summary(entry: &Entry, root_path: &Path) -> String {
+  match entry.meta.as_ref().and_then(|meta| meta.summary.clone()) {
+    Some(summary) => summary,
+    None => extract_description(&root_path.join(entry.url.as_str())),
+  }
+}
+
+fn generate_feeds(root: &Entry, root_path: &Path, atom_path: &Path, rss_path: &Path) -> Result<(), Box<dyn Error>> {
+  let mut posts = Vec::new();
+  collect_posts(&mut posts, root, &feed_excluded());
+  posts.sort_by(|a, b| post_date(b).cmp(&post_date(a)).then(a.url.cmp(&b.url)));
+  posts.truncate(feed_limit());
+  let updated = posts.first().map(|post| post_date(post)).unwrap_or(root.modified);
+
+  let mut atom_entries = Vec::new();
+  let mut rss_items = Vec::new();
+  for post in posts {
+    let link = format!("{}/{}/", BASE_URL, post.url);
+    let title = escape_html(post_title(post));
+    let summary = escape_html(&post_summary(post, root_path));
+    let date = DateTime::<Utc>::from_timestamp(post_date(post) as i64, 0).unwrap_or_default();
+    atom_entries.push(format!(
+      "  <entry>\n    <title>{}</title>\n    <link href=\"{}\"/>\n    <id>{}</id>\n    <updated>{}</updated>\n    <summary>{}</summary>\n  </entry>",
+      title,
+      link,
+      link,
+      date.to_rfc3339(),
+      summary
+    ));
+    rss_items.push(format!(
+      "    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <guid>{}</guid>\n      <pubDate>{}</pubDate>\n      <description>{}</description>\n    </item>",
+      title,
+      link,
+      link,
+      date.to_rfc2822(),
+      summary
+    ));
+  }
+
+  let updated = DateTime::<Utc>::from_timestamp(updated as i64, 0).unwrap_or_default();
+  let atom = format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n  <title>{}</title>\n  <link href=\"{}/\"/>\n  <link href=\"{}/feed.xml\" rel=\"self\"/>\n  <id>{}/</id>\n  <updated>{}</updated>\n  <author>\n    <name>{}</name>\n  </author>\n{}\n</feed>",
+    SITE_NAME,
+    BASE_URL,
+    BASE_URL,
+    BASE_URL,
+    updated.to_rfc3339(),
+    SITE_NAME,
+    atom_entries.join("\n")
+  );
+  let rss = format!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n  <channel>\n    <title>{}</title>\n    <link>{}/</link>\n    <description>Posts of {}</description>\n    <atom:link href=\"{}/rss.xml\" rel=\"self\" type=\"application/rss+xml\"/>\n    <lastBuildDate>{}</lastBuildDate>\n{}\n  </channel>\n</rss>",
+    SITE_NAME,
+    BASE_URL,
+    SITE_NAME,
+    BASE_URL,
+    updated.to_rfc2822(),
+    rss_items.join("\n")
+  );
+
+  fs::write(atom_path, atom)?;
+  fs::write(rss_path, rss)?;
+  Ok(())
+}
+
 fn format_timestamp(timestamp: u64) -> String {
   let datetime = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
     .unwrap_or_else(|| Utc::now());
   datetime.format("%Y-%m-%d").to_string()
 }
 
-fn collect_urls(urls: &mut Vec<String>, entry: &Entry, parent_url: &str) {
-  for (_filename, child) in entry.entries.iter() {
+fn collect_urls(urls: &mut Vec<String>, root: &Entry, entry: &Entry, parent_url: &str) {
+  for child in children(root, entry) {
     let url = format!("{}/{}", parent_url, child.filename);
     let clean_url = url.trim_start_matches('/');
 
-    // Skip res and img directories, and latest.md
-    if clean_url.starts_with("res/") || clean_url.starts_with("img/") ||
-       clean_url.contains("/res/") || clean_url.contains("/img/") ||
-       clean_url == "latest.md" {
+    // Skip res and img directories, and links like latest.md
+    if is_skipped(clean_url) || child.link.is_some() {
       continue;
     }
 
@@ -95,60 +618,75 @@ fn collect_urls(urls: &mut Vec<String>, entry: &Entry, parent_url: &str) {
       priority
     ));
 
-    // If URL contains "old/", also add a fallback URL without it
-    if clean_url.contains("/old/") {
-      let fallback_url = clean_url.replace("/old/", "/");
-      urls.push(format!(
-        "  <url>\n    <loc>{}/{}/</loc>\n    <lastmod>{}</lastmod>\n    <changefreq>{}</changefreq>\n    <priority>{}</priority>\n  </url>",
-        BASE_URL,
-        fallback_url,
-        format_timestamp(child.modified),
-        changefreq,
-        priority
-      ));
-    }
-
     // Recursively collect URLs from subdirectories
     if child.is_dir {
-      collect_urls(urls, child, &url);
+      collect_urls(urls, root, child, &url);
     }
   }
 }
 
-fn create_dirs(root: &Entry, index_path: &Path) {
+// children of a directory including the ones unioned into it by UNION_MOUNTS
+fn children<'a>(root: &'a Entry, dir: &'a Entry) -> Vec<&'a Entry> {
+  let mut children: Vec<&Entry> = dir.entries.values().collect();
+  for (_target, source) in UNION_MOUNTS
+    .iter()
+    .filter(|(target, _source)| target.trim_matches('/') == dir.url.as_str())
+  {
+    let source_dir = source
+      .split('/')
+      .filter(|name| !name.is_empty())
+      .try_fold(root, |entry, name| entry.entries.values().find(|child| child.filename.as_str() == name));
+    if let Some(source_dir) = source_dir {
+      children.extend(
+        source_dir
+          .entries
+          .values()
+          .filter(|child| !dir.entries.contains_key(&child.filename)),
+      );
+    }
+  }
+  children
+}
+
+fn create_dirs(root: &Entry, root_path: &Path, index_path: &Path) {
   let index_template = fs::read_to_string(index_path).unwrap();
-  create_dirs_with_template(root, &index_template);
+  create_dirs_with_template(root, root_path, root, "", &index_template);
 }
 
-fn create_dirs_with_template(root: &Entry, index_template: &str) {
-  let _ = fs::create_dir_all(PARENT_URL.to_string() + &root.url);
+fn create_dirs_with_template(root: &Entry, root_path: &Path, entry: &Entry, url: &str, index_template: &str) {
+  let _ = fs::create_dir_all(PARENT_URL.to_string() + url);
 
-  for (_filename, entry) in root.entries.iter() {
-    create_dirs_with_template(entry, index_template);
+  for child in children(root, entry) {
+    let child_url = format!("{}/{}", url, child.filename);
+    create_dirs_with_template(root, root_path, child, child_url.trim_start_matches('/'), index_template);
   }
 
-  if root.is_dir {
+  if entry.is_dir {
     // For directories, create an index with a description of the contents
-    let description = generate_directory_description(root);
-    write_index_html_for_entry(root, &description, index_template);
+    let description = generate_directory_description(root, entry);
+    write_index_html_for_entry(root, entry, url, &description, None, index_template);
   } else {
-    let description = get_file_description(&root.url);
-    write_index_html_for_entry(root, &description, index_template);
-
-    if root.url.contains("old/")  {
-      let fallback_url = root.url.replace("old/", "");
-      let _ = fs::create_dir_all(PARENT_URL.to_string() + &fallback_url);
-      let mut fallback_entry = root.clone();
-      fallback_entry.url = Box::new(fallback_url);
-      write_index_html_for_entry(&fallback_entry, &description, index_template);
-    }
+    let description = match entry.meta.as_ref().and_then(|meta| meta.summary.clone()) {
+      Some(summary) => summary,
+      None => get_file_description(&root_path.join(entry.url.as_str())),
+    };
+    // the rendered file for readers without javascript, links would only repeat their target
+    let content = match entry.link {
+      Some(_) => None,
+      None => fs::read_to_string(content_path(root_path, entry)).ok(),
+    };
+    let content = content.as_deref().map(|content| match entry.meta {
+      Some(_) => text::body(content),
+      None => content,
+    });
+    write_index_html_for_entry(root, entry, url, &description, content, index_template);
   }
 }
 
-fn generate_directory_description(entry: &Entry) -> String {
+fn generate_directory_description(root: &Entry, entry: &Entry) -> String {
   let mut file_list = Vec::new();
 
-  for (_filename, child) in entry.entries.iter() {
+  for child in children(root, entry) {
     if !child.filename.starts_with(".") {
       file_list.push(child.filename.as_str());
     }
@@ -166,23 +704,29 @@ fn generate_directory_description(entry: &Entry) -> String {
   }
 }
 
-fn get_file_description(entry_path: &str) -> String {
-  let cargo_dir = env::var_os("CARGO_MANIFEST_DIR").unwrap();
-  let md_path = Path::new(&cargo_dir).join(entry_path);
-
+fn get_file_description(md_path: &Path) -> String {
   if md_path.exists() && md_path.is_file() {
-    extract_description(&md_path)
+    extract_description(md_path)
   } else {
     String::new()
   }
 }
 
-fn write_index_html_for_entry(entry: &Entry, description: &str, index_template: &str) {
+fn write_index_html_for_entry(
+  root: &Entry,
+  entry: &Entry,
+  url: &str,
+  description: &str,
+  content: Option<&str>,
+  index_template: &str,
+) {
   // Extract filename from URL
-  let filename = entry.url.trim_end_matches('/').split('/').last().unwrap_or(&entry.url);
+  let filename = url.trim_end_matches('/').split('/').last().unwrap_or(url);
   // Use "gfelber.dev" as display name for root directory
-  let display_name = if filename.is_empty() || entry.url.is_empty() || entry.url.as_str() == "" {
-    "gfelber.dev"
+  let display_name = if filename.is_empty() || url.is_empty() {
+    SITE_NAME
+  } else if let Some(title) = entry.meta.as_ref().and_then(|meta| meta.title.as_deref()) {
+    title
   } else {
     filename
   };
@@ -205,15 +749,23 @@ fn write_index_html_for_entry(entry: &Entry, description: &str, index_template:
     );
   }
   
+  // Add link previews for social sites and search engines, the charset has to stay in the first 1024 bytes
+  modified_html = modified_html.replacen(
+    CHARSET_META,
+    &format!("{}\n    {}", CHARSET_META, social_meta(entry, url, display_name, description).join("\n    ")),
+    1
+  );
+
   // Add hidden h1 with filename and links after <body>
   let mut hidden_content = format!("<h1 style=\"display: none;\">{}</h1>", escaped_display_name);
   
   // For directories, add hidden links to all entries
   if entry.is_dir {
     let mut links = Vec::new();
-    for (_filename, child) in entry.entries.iter() {
+    for child in children(root, entry) {
       if !child.filename.starts_with(".") {
-        let child_url = format!("/{}/", child.url.trim_end_matches('/'));
+        let child_url = format!("{}/{}/", url, child.filename);
+        let child_url = format!("/{}", child_url.trim_start_matches('/'));
         let escaped_child_name = escape_html(&child.filename);
         links.push(format!("<a href=\"{}\">{}</a>", child_url, escaped_child_name));
       }
@@ -228,10 +780,130 @@ fn write_index_html_for_entry(entry: &Entry, description: &str, index_template:
     &format!("<body>\n    {}", hidden_content)
   );
 
-  let dest_path = PARENT_URL.to_string() + &entry.url + "/index.html";
+  // Add the file content with its colors for readers without javascript
+  if let Some(content) = content {
+    modified_html = modified_html.replace(
+      "<noscript>",
+      &format!("<noscript>\n      <article class=\"ansi\"><pre>{}</pre></article>", ansi::to_html(content))
+    );
+  }
+
+  let dest_path = PARENT_URL.to_string() + url + "/index.html";
   let _ = fs::write(dest_path, modified_html);
 }
 
+// Open Graph and Twitter card tags, files are articles with BlogPosting JSON-LD
+fn social_meta(entry: &Entry, url: &str, title: &str, description: &str) -> Vec<String> {
+  let page_url = if url.is_empty() {
+    format!("{}/", BASE_URL)
+  } else {
+    format!("{}/{}/", BASE_URL, url.trim_matches('/'))
+  };
+  let is_article = !entry.is_dir;
+  let image = entry
+    .meta
+    .as_ref()
+    .and_then(|meta| meta.image.as_deref())
+    .map(|image| image_url(url, image));
+  let published = entry.meta.as_ref().and_then(|meta| meta.date).unwrap_or(entry.created);
+  // a declared date can be newer than the commit that added it
+  let modified = format_rfc3339(entry.modified.max(published));
+  let published = format_rfc3339(published);
+
+  let mut properties = vec![
+    ("og:site_name", SITE_NAME.to_string()),
+    ("og:title", title.to_string()),
+    ("og:url", page_url.clone()),
+    ("og:type", if is_article { "article" } else { "website" }.to_string()),
+  ];
+  if !description.is_empty() {
+    properties.push(("og:description", description.to_string()));
+  }
+  if let Some(image) = &image {
+    properties.push(("og:image", image.clone()));
+  }
+  if is_article {
+    properties.push(("article:published_time", published.clone()));
+    properties.push(("article:modified_time", modified.clone()));
+    for tag in entry.meta.iter().flat_map(|meta| &meta.tags) {
+      properties.push(("article:tag", tag.clone()));
+    }
+  }
+  let mut tags: Vec<String> = properties
+    .iter()
+    .map(|(property, content)| format!("<meta property=\"{}\" content=\"{}\" />", property, escape_html(content)))
+    .collect();
+
+  let card = if image.is_some() { "summary_large_image" } else { "summary" };
+  let mut names = vec![("twitter:card", card.to_string()), ("twitter:title", title.to_string())];
+  if !description.is_empty() {
+    names.push(("twitter:description", description.to_string()));
+  }
+  if let Some(image) = &image {
+    names.push(("twitter:image", image.clone()));
+  }
+  tags.extend(
+    names
+      .iter()
+      .map(|(name, content)| format!("<meta name=\"{}\" content=\"{}\" />", name, escape_html(content))),
+  );
+
+  if is_article {
+    let mut posting = serde_json::json!({
+      "@context": "https://schema.org",
+      "@type": "BlogPosting",
+      "headline": title,
+      "url": page_url,
+      "mainEntityOfPage": page_url,
+      "datePublished": published,
+      "dateModified": modified,
+      "author": { "@type": "Person", "name": author(), "url": format!("{}/", BASE_URL) },
+      "publisher": { "@type": "Organization", "name": SITE_NAME, "url": format!("{}/", BASE_URL) },
+    });
+    if !description.is_empty() {
+      posting["description"] = description.into();
+    }
+    if let Some(image) = &image {
+      posting["image"] = image.as_str().into();
+    }
+    if let Some(meta) = entry.meta.as_ref().filter(|meta| !meta.tags.is_empty()) {
+      posting["keywords"] = meta.tags.join(", ").into();
+    }
+    // keep the script element closed only by its end tag
+    let json = posting.to_string().replace('<', "\\u003c");
+    tags.push(format!("<script type=\"application/ld+json\">{}</script>", json));
+  }
+  tags
+}
+
+// first package author without the mail address
+fn author() -> String {
+  let authors = env::var("CARGO_PKG_AUTHORS").unwrap_or_default();
+  let author = authors.split(':').next().unwrap_or_default();
+  author.split(" <").next().unwrap_or_default().trim().to_string()
+}
+
+// image paths are relative to the post, or to the content root with a leading /, which is served under /root
+fn image_url(url: &str, path: &str) -> String {
+  if path.starts_with("http://") || path.starts_with("https://") {
+    return path.to_string();
+  }
+  let path = match path.strip_prefix('/') {
+    Some(path) => path.to_string(),
+    None => match url.trim_matches('/').rsplit_once('/') {
+      Some((parent, _name)) => format!("{}/{}", parent, path),
+      None => path.to_string(),
+    },
+  };
+  format!("{}/root/{}", BASE_URL, path)
+}
+
+fn format_rfc3339(timestamp: u64) -> String {
+  DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+    .unwrap_or_default()
+    .to_rfc3339()
+}
+
 fn escape_html(text: &str) -> String {
   text
     .replace("&", "&amp;")
@@ -242,7 +914,7 @@ fn escape_html(text: &str) -> String {
 
 fn extract_description(md_path: &Path) -> String {
   let content = match fs::read_to_string(md_path) {
-    Ok(c) => c,
+    Ok(c) => text::body(&c).to_string(),
     Err(_) => return String::new(),
   };
 
@@ -284,38 +956,67 @@ fn extract_description(md_path: &Path) -> String {
 
 fn visit_dirs<'a>(
   root: &'a mut Entry,
+  root_path: &'a Path,
   dir: &'a Path,
   url: &'a str,
+  dates: &'a HashMap<String, (u64, u64)>,
 ) -> Result<&'a mut Entry, Box<dyn Error>> {
   if dir.is_dir() {
     for entry in fs::read_dir(dir)? {
       let dir_entry = entry?;
       let path = dir_entry.path();
-      let entry_metadata = if dir_entry.file_type().unwrap().is_symlink() {
-        dir_entry.path().metadata().unwrap()
+      // keep symlinks as links instead of copying their target
+      let is_link = dir_entry.file_type().unwrap().is_symlink();
+      let entry_metadata = path.symlink_metadata().unwrap();
+      let link = if is_link {
+        Some(link_target(root_path, &path)?)
       } else {
-        path.metadata().unwrap()
+        None
       };
       let filename = dir_entry.file_name().to_string_lossy().to_string();
       let filename_box = Box::new(filename.clone());
       let fileurl = url.to_string() + &filename;
+      let is_dir = !is_link && path.is_dir();
+      let meta = if is_link || is_dir { None } else { read_meta(&path) };
+      if meta.as_ref().is_some_and(|meta| meta.draft) && !drafts_enabled() {
+        continue;
+      }
+      let rendered = if is_link || is_dir { None } else { render_markdown(&path, &fileurl)? };
+      let sha256 = if is_link || is_dir {
+        None
+      } else if rendered.is_some() {
+        Some(format!("{:x}", Sha256::digest(fs::read(rendered_path(&fileurl))?)))
+      } else {
+        Some(format!("{:x}", Sha256::digest(fs::read(&path)?)))
+      };
+      let mtime = entry_metadata
+        .modified()
+        .unwrap()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+      let (modified, created) = entry_dates(dates, &fileurl, mtime);
       let mut entry: Entry = Entry {
         filename: filename_box.clone(),
         url: Box::new(fileurl.clone()),
-        size: entry_metadata.len(),
-        modified: entry_metadata
-          .modified()
-          .unwrap()
-          .duration_since(SystemTime::UNIX_EPOCH)
-          .unwrap()
-          .as_secs(),
-        is_dir: path.is_dir(),
+        size: rendered.unwrap_or(entry_metadata.len()),
+        mode: entry_metadata.mode() & 0o7777,
+        nlink: entry_metadata.nlink(),
+        owner: id_name(entry_metadata.uid()),
+        group: id_name(entry_metadata.gid()),
+        modified,
+        created,
+        is_dir,
+        rendered: rendered.is_some(),
+        link,
+        sha256,
+        meta,
         entries: HashMap::new(),
       };
 
-      if path.is_dir() {
+      if entry.is_dir {
         // It's a subdirectory, so visit it recursively
-        visit_dirs(&mut entry, path.as_path(), &(fileurl + "/"))?;
+        visit_dirs(&mut entry, root_path, path.as_path(), &(fileurl + "/"), dates)?;
       }
 
       root.entries.insert(filename_box, entry);
@@ -325,12 +1026,180 @@ fn visit_dirs<'a>(
   Ok(root)
 }
 
-#[derive(Serialize, Clone)]
+// generated terminal views of raw markdown, copied over the content tree by webpack
+const RENDERED_URL: &str = "rendered/";
+
+fn rendered_path(url: &str) -> PathBuf {
+  let cargo_dir = env::var_os("CARGO_MANIFEST_DIR").unwrap();
+  Path::new(&cargo_dir).join(RENDERED_URL).join(url)
+}
+
+// where the content that is served for a file is
+fn content_path(root_path: &Path, entry: &Entry) -> PathBuf {
+  if entry.rendered {
+    rendered_path(&entry.url)
+  } else {
+    root_path.join(entry.url.as_str())
+  }
+}
+
+// renders raw markdown for the terminal and returns the rendered size
+// files with escape sequences were rendered before (by glow) and are served as they are
+fn render_markdown(path: &Path, url: &str) -> Result<Option<u64>, Box<dyn Error>> {
+  if path.extension().is_none_or(|extension| extension != "md") {
+    return Ok(None);
+  }
+  let Ok(content) = fs::read_to_string(path) else {
+    return Ok(None);
+  };
+  if content.contains('\x1b') {
+    return Ok(None);
+  }
+  let rendered = markdown::render(text::body(&content));
+  let rendered_path = rendered_path(url);
+  fs::create_dir_all(rendered_path.parent().unwrap())?;
+  fs::write(&rendered_path, &rendered)?;
+  Ok(Some(rendered.len() as u64))
+}
+
+// url -> (last, first) commit time of everything below the content tree, empty without git
+// directories take the dates of their contents, the root has the url ""
+fn git_dates(root_path: &Path) -> HashMap<String, (u64, u64)> {
+  let mut dates = HashMap::new();
+  let output = Command::new("git")
+    .args(["-c", "core.quotePath=false", "log", "--format=commit %ct", "--name-only", "--relative", "--", "."])
+    .current_dir(root_path)
+    .output();
+  let output = match output {
+    Ok(output) if output.status.success() => output,
+    _ => return dates,
+  };
+  watch_history(root_path);
+  // newest commits come first
+  let mut time = 0;
+  for line in String::from_utf8_lossy(&output.stdout).lines() {
+    if let Some(commit) = line.strip_prefix("commit ") {
+      time = commit.parse().unwrap_or(0);
+      continue;
+    }
+    if line.is_empty() {
+      continue;
+    }
+    let mut url = line;
+    loop {
+      let (modified, created) = dates.entry(url.to_string()).or_insert((time, time));
+      *modified = (*modified).max(time);
+      *created = (*created).min(time);
+      if url.is_empty() {
+        break;
+      }
+      url = url.rsplit_once('/').map_or("", |(parent, _name)| parent);
+    }
+  }
+  dates
+}
+
+// new commits move HEAD or the branch it points to, branches can also be in packed-refs
+fn watch_history(root_path: &Path) {
+  let output = Command::new("git")
+    .args(["rev-parse", "--path-format=absolute", "--git-dir", "--git-common-dir", "--symbolic-full-name", "HEAD"])
+    .current_dir(root_path)
+    .output();
+  let Ok(output) = output else {
+    return;
+  };
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  let mut lines = stdout.lines();
+  let (Some(git_dir), Some(common_dir)) = (lines.next(), lines.next()) else {
+    return;
+  };
+  let (git_dir, common_dir) = (Path::new(git_dir), Path::new(common_dir));
+  let mut files = vec![git_dir.join("HEAD"), common_dir.join("packed-refs")];
+  // a detached HEAD has no branch
+  if let Some(branch) = lines.next().filter(|branch| branch.starts_with("refs/")) {
+    files.push(common_dir.join(branch));
+  }
+  // cargo reruns the script every time if a listed file is missing
+  for file in files.iter().filter(|file| file.exists()) {
+    println!("cargo:rerun-if-changed={}", file.display());
+  }
+}
+
+// SOURCE_DATE_EPOCH is the build time of reproducible builds, nothing is newer than it
+fn source_date_epoch() -> Option<u64> {
+  static EPOCH: OnceLock<Option<u64>> = OnceLock::new();
+  *EPOCH.get_or_init(|| {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    env::var("SOURCE_DATE_EPOCH").ok()?.parse().ok()
+  })
+}
+
+// (modified, created) from the history, fallback for files that were never committed
+fn entry_dates(dates: &HashMap<String, (u64, u64)>, url: &str, fallback: u64) -> (u64, u64) {
+  let (modified, created) = dates.get(url).copied().unwrap_or((fallback, fallback));
+  match source_date_epoch() {
+    Some(epoch) => (modified.min(epoch), created.min(epoch)),
+    None => (modified, created),
+  }
+}
+
+// owner and group in listings, the accounts of the build host stay out of the tree
+// id 0 is root, every other id is the site owner, SITE_OWNER overrides its name
+fn id_name(id: u32) -> String {
+  static OWNER: OnceLock<String> = OnceLock::new();
+  if id == 0 {
+    return "root".to_string();
+  }
+  OWNER
+    .get_or_init(|| {
+      println!("cargo:rerun-if-env-changed=SITE_OWNER");
+      env::var("SITE_OWNER")
+        .ok()
+        .filter(|owner| !owner.is_empty())
+        .unwrap_or(SITE_OWNER.to_string())
+    })
+    .clone()
+}
+
+fn link_target(root_path: &Path, path: &Path) -> Result<String, Box<dyn Error>> {
+  let target = fs::read_link(path)?;
+  // absolute targets inside the content tree become absolute site paths
+  if target.is_absolute() {
+    let root_path = root_path.canonicalize()?;
+    if let Ok(relative) = target.strip_prefix(&root_path) {
+      return Ok(format!("/{}", relative.to_string_lossy()));
+    }
+  }
+  Ok(target.to_string_lossy().to_string())
+}
+
+#[derive(Clone)]
 struct Entry {
   filename: Box<String>,
   url: Box<String>,
   size: u64,
+  mode: u32,
+  nlink: u64,
+  owner: String,
+  group: String,
   modified: u64,
+  created: u64,
   is_dir: bool,
+  rendered: bool, // only applicable to markdown Files without escape sequences
+  link: Option<String>, // only applicable to Links
+  sha256: Option<String>, // only applicable to Files
+  meta: Option<Meta>, // only applicable to markdown Files with front matter
   entries: HashMap<Box<String>, Entry>, // only applicable to Dirs
 }
+
+#[derive(Serialize, Clone)]
+struct Meta {
+  title: Option<String>,
+  date: Option<u64>,
+  tags: Vec<String>,
+  summary: Option<String>,
+  draft: bool,
+  // preview image of shared links, only used by the generated pages
+  #[serde(skip)]
+  image: Option<String>,
+}
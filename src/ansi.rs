@@ -0,0 +1,269 @@
+// ANSI escape sequences to HTML, build.rs includes this file for the static page content, so it can't use the crate
+// the 16 terminal colors become ansi-fg-N/ansi-bg-N classes styled like the xterm theme, other colors inline styles
+
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+  Indexed(u8),
+  Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Default, PartialEq)]
+struct Style {
+  bold: bool,
+  dim: bool,
+  italic: bool,
+  underline: bool,
+  reverse: bool,
+  strike: bool,
+  fg: Option<Color>,
+  bg: Option<Color>,
+}
+
+impl Style {
+  // style after a SGR sequence with the given parameters, e.g. "1;38;5;203"
+  fn apply(&self, params: &str) -> Style {
+    let mut style = self.clone();
+    let codes: Vec<u8> = params.split(';').map(|code| code.parse().unwrap_or(0)).collect();
+    let mut codes = codes.into_iter();
+    while let Some(code) = codes.next() {
+      match code {
+        0 => style = Style::default(),
+        1 => style.bold = true,
+        2 => style.dim = true,
+        3 => style.italic = true,
+        4 => style.underline = true,
+        7 => style.reverse = true,
+        9 => style.strike = true,
+        21 | 22 => {
+          style.bold = false;
+          style.dim = false;
+        }
+        23 => style.italic = false,
+        24 => style.underline = false,
+        27 => style.reverse = false,
+        29 => style.strike = false,
+        30..=37 => style.fg = Some(Color::Indexed(code - 30)),
+        38 => style.fg = extended(&mut codes),
+        39 => style.fg = None,
+        40..=47 => style.bg = Some(Color::Indexed(code - 40)),
+        48 => style.bg = extended(&mut codes),
+        49 => style.bg = None,
+        90..=97 => style.fg = Some(Color::Indexed(code - 90 + 8)),
+        100..=107 => style.bg = Some(Color::Indexed(code - 100 + 8)),
+        _ => {}
+      }
+    }
+    style
+  }
+
+  fn open(&self) -> String {
+    let mut classes = vec![];
+    let mut styles = vec![];
+    for (set, class) in [
+      (self.bold, "ansi-bold"),
+      (self.dim, "ansi-dim"),
+      (self.italic, "ansi-italic"),
+      (self.underline, "ansi-underline"),
+      (self.strike, "ansi-strike"),
+    ] {
+      if set {
+        classes.push(class.to_string());
+      }
+    }
+    let (fg, bg) = if self.reverse { (self.bg, self.fg) } else { (self.fg, self.bg) };
+    match fg {
+      Some(Color::Indexed(index)) if index < 16 => classes.push(format!("ansi-fg-{}", index)),
+      Some(color) => styles.push(format!("color: {}", hex(color))),
+      // reversed default colors
+      None if self.reverse => classes.push("ansi-reverse-fg".to_string()),
+      None => {}
+    }
+    match bg {
+      Some(Color::Indexed(index)) if index < 16 => classes.push(format!("ansi-bg-{}", index)),
+      Some(color) => styles.push(format!("background: {}", hex(color))),
+      None if self.reverse => classes.push("ansi-reverse-bg".to_string()),
+      None => {}
+    }
+    let mut span = "<span".to_string();
+    if !classes.is_empty() {
+      span.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+    }
+    if !styles.is_empty() {
+      span.push_str(&format!(" style=\"{}\"", styles.join("; ")));
+    }
+    span.push('>');
+    span
+  }
+}
+
+// 38/48;5;N and 38/48;2;R;G;B
+fn extended(codes: &mut impl Iterator<Item = u8>) -> Option<Color> {
+  match codes.next()? {
+    5 => Some(Color::Indexed(codes.next()?)),
+    2 => Some(Color::Rgb(codes.next()?, codes.next()?, codes.next()?)),
+    _ => None,
+  }
+}
+
+// colors past the first 16 of the 256 color palette, a 6x6x6 cube and a gray ramp
+fn hex(color: Color) -> String {
+  const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+  let (r, g, b) = match color {
+    Color::Rgb(r, g, b) => (r, g, b),
+    Color::Indexed(index @ 16..=231) => {
+      let index = index - 16;
+      (
+        LEVELS[(index / 36) as usize],
+        LEVELS[(index / 6 % 6) as usize],
+        LEVELS[(index % 6) as usize],
+      )
+    }
+    Color::Indexed(index) => {
+      let gray = 8 + 10 * index.saturating_sub(232);
+      (gray, gray, gray)
+    }
+  };
+  format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('"', "&quot;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+// text with SGR colors and OSC 8 links as HTML for a <pre>, other sequences are dropped
+pub fn to_html(text: &str) -> String {
+  let mut html = String::with_capacity(text.len());
+  let mut style = Style::default();
+  let mut span_open = false;
+  let mut link_open = false;
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '\x1b' {
+      // spans only open once they have text
+      if !span_open && style != Style::default() {
+        html.push_str(&style.open());
+        span_open = true;
+      }
+      match c {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        _ => html.push(c),
+      }
+      continue;
+    }
+    match chars.next() {
+      // CSI, ends with a byte in @..~
+      Some('[') => {
+        let mut params = String::new();
+        let mut command = None;
+        for c in chars.by_ref() {
+          if ('@'..='~').contains(&c) {
+            command = Some(c);
+            break;
+          }
+          params.push(c);
+        }
+        if command != Some('m') {
+          continue;
+        }
+        let next = style.apply(&params);
+        if next != style && span_open {
+          html.push_str("</span>");
+          span_open = false;
+        }
+        style = next;
+      }
+      // OSC, ends with BEL or ESC \
+      Some(']') => {
+        let mut osc = String::new();
+        while let Some(c) = chars.next() {
+          if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+            break;
+          }
+          osc.push(c);
+        }
+        // hyperlinks are ESC ] 8 ; params ; uri, an empty uri ends the link
+        let Some(link) = osc.strip_prefix("8;") else {
+          continue;
+        };
+        let uri = link.split_once(';').map_or("", |(_params, uri)| uri);
+        if span_open {
+          html.push_str("</span>");
+          span_open = false;
+        }
+        if link_open {
+          html.push_str("</a>");
+          link_open = false;
+        }
+        if !uri.is_empty() {
+          html.push_str(&format!("<a href=\"{}\">", escape(uri)));
+          link_open = true;
+        }
+      }
+      _ => {}
+    }
+  }
+  if span_open {
+    html.push_str("</span>");
+  }
+  if link_open {
+    html.push_str("</a>");
+  }
+  html
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn basic_colors_are_classes() {
+    assert_eq!(to_html("\x1b[31mred\x1b[0m plain"), "<span class=\"ansi-fg-1\">red</span> plain");
+    assert_eq!(to_html("\x1b[1;94;40mx"), "<span class=\"ansi-bold ansi-fg-12 ansi-bg-0\">x</span>");
+  }
+
+  #[test]
+  fn extended_colors_are_styles() {
+    assert_eq!(
+      to_html("\x1b[38;5;203;48;5;244mx"),
+      "<span style=\"color: #ff5f5f; background: #808080\">x</span>"
+    );
+    assert_eq!(to_html("\x1b[38;2;1;2;3mx"), "<span style=\"color: #010203\">x</span>");
+  }
+
+  #[test]
+  fn reverse_swaps_colors() {
+    assert_eq!(to_html("\x1b[7;31mx"), "<span class=\"ansi-reverse-fg ansi-bg-1\">x</span>");
+    assert_eq!(to_html("\x1b[7mx"), "<span class=\"ansi-reverse-fg ansi-reverse-bg\">x</span>");
+  }
+
+  #[test]
+  fn text_is_escaped() {
+    assert_eq!(to_html("<a & b>"), "&lt;a &amp; b&gt;");
+  }
+
+  #[test]
+  fn links_become_anchors() {
+    assert_eq!(
+      to_html("\x1b]8;;https://example.com/?a=1&b=\"2\"\x1b\\link\x1b]8;;\x1b\\ after"),
+      "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">link</a> after"
+    );
+    // BEL ends a sequence as well
+    assert_eq!(to_html("\x1b]8;;https://example.com\x07link\x1b]8;;\x07"), "<a href=\"https://example.com\">link</a>");
+  }
+
+  #[test]
+  fn other_sequences_are_dropped() {
+    assert_eq!(to_html("\x1b[2Kclear\x1b]0;title\x07"), "clear");
+  }
+
+  #[test]
+  fn styles_without_text_open_no_span() {
+    assert_eq!(to_html("\x1b[1m\x1b[0mplain"), "plain");
+  }
+}
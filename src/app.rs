@@ -3,6 +3,10 @@ use crate::termstate::TermState;
 pub trait App: Send + Sync {
   fn readchar(&mut self, state: &mut TermState, input: char) -> Option<Box<dyn App>>;
   fn scroll(&mut self, _state: &mut TermState, _lines: i32) {}
+  // the terminal changed its size, state has the new one
+  fn resize(&mut self, _state: &mut TermState) {}
+  // all input of one readline call was read, escape sequences arrive in a single call
+  fn input_end(&mut self, _state: &mut TermState) {}
   fn autocomplete(&self, state: &TermState) -> Vec<String>;
 }
 
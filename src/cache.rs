@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::utils::{self, Fetched, Validators};
+
+// upper bound for the content kept in memory
+pub const MAX_BYTES: usize = 4 * 1024 * 1024;
+// seconds content is served without asking the server if it changed
+const FRESH_SECS: i64 = 5 * 60;
+
+struct Cached {
+  // content hash (or modification stamp) of the entry the content was loaded for
+  version: String,
+  content: String,
+  validators: Validators,
+  checked: i64,
+  used: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Stats {
+  pub entries: usize,
+  pub bytes: usize,
+  pub hits: u64,
+  pub revalidated: u64,
+  pub misses: u64,
+  pub evictions: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+  files: HashMap<String, Cached>,
+  // increases with every access, the smallest `used` is evicted first
+  tick: u64,
+  stats: Stats,
+}
+
+// what the cache has for a file before it is fetched
+enum Lookup {
+  Fresh(String),
+  // cached but old enough to ask the server, with the validators of the cached response
+  Stale(Validators),
+  Missing,
+}
+
+lazy_static! {
+  static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+}
+
+// content of a static file, only hits the network if it isn't cached or has gone stale
+pub async fn load(url: &str, modified: u64, sha256: Option<&str>) -> Result<String, String> {
+  let now = chrono::Utc::now().timestamp();
+  // hashes stay the same across deploys that didn't touch the file
+  let version = match sha256 {
+    Some(sha256) => sha256.to_string(),
+    None => modified.to_string(),
+  };
+  let mut validators = match CACHE.lock().unwrap().lookup(url, &version, now) {
+    Lookup::Fresh(content) => return Ok(content),
+    Lookup::Stale(validators) => validators,
+    Lookup::Missing => Validators::default(),
+  };
+
+  loop {
+    let revalidating = validators.etag.is_some() || validators.last_modified.is_some();
+    let fetched = utils::afetch(url, &validators).await?;
+    let mut cache = CACHE.lock().unwrap();
+    match fetched {
+      Fetched::NotModified => match cache.revalidated(url, now) {
+        Some(content) => return Ok(content),
+        // evicted while the request was running, fetch the content without validators
+        None if revalidating => validators = Validators::default(),
+        None => return Err("HTTP request failed".to_string()),
+      },
+      Fetched::Content(content, validators) => {
+        if sha256.is_some_and(|sha256| sha256 != utils::sha256_hex(&content)) {
+          warn!("{}: content doesn't match the build, stale copy?", url);
+        }
+        cache.insert(url, version, &content, validators, now);
+        return Ok(content);
+      }
+    }
+  }
+}
+
+pub fn stats() -> Stats {
+  let cache = CACHE.lock().unwrap();
+  Stats {
+    entries: cache.files.len(),
+    ..cache.stats.clone()
+  }
+}
+
+pub fn clear() {
+  let mut cache = CACHE.lock().unwrap();
+  cache.files.clear();
+  cache.stats.bytes = 0;
+}
+
+impl Cache {
+  fn lookup(&mut self, url: &str, version: &str, now: i64) -> Lookup {
+    self.tick += 1;
+    let tick = self.tick;
+    match self.files.get_mut(url) {
+      Some(cached) if cached.version == version => {
+        cached.used = tick;
+        if now - cached.checked >= FRESH_SECS {
+          return Lookup::Stale(cached.validators.clone());
+        }
+        let content = cached.content.clone();
+        self.stats.hits += 1;
+        Lookup::Fresh(content)
+      }
+      // rebuilt site, the old content can't be revalidated
+      Some(_) => {
+        self.remove(url);
+        Lookup::Missing
+      }
+      None => Lookup::Missing,
+    }
+  }
+
+  // content of a 304, None if it was evicted while the request was running
+  fn revalidated(&mut self, url: &str, now: i64) -> Option<String> {
+    let cached = self.files.get_mut(url)?;
+    info!("revalidated {}", url);
+    cached.checked = now;
+    let content = cached.content.clone();
+    self.stats.revalidated += 1;
+    Some(content)
+  }
+
+  fn insert(&mut self, url: &str, version: String, content: &str, validators: Validators, now: i64) {
+    self.stats.misses += 1;
+    self.remove(url);
+    if content.len() > MAX_BYTES {
+      return;
+    }
+    let used = self.tick;
+    self.stats.bytes += content.len();
+    self.files.insert(
+      url.to_string(),
+      Cached {
+        version,
+        content: content.to_string(),
+        validators,
+        checked: now,
+        used,
+      },
+    );
+    self.evict();
+  }
+
+  fn remove(&mut self, url: &str) {
+    if let Some(cached) = self.files.remove(url) {
+      self.stats.bytes -= cached.content.len();
+    }
+  }
+
+  fn evict(&mut self) {
+    while self.stats.bytes > MAX_BYTES {
+      let Some(url) = self
+        .files
+        .iter()
+        .min_by_key(|(_url, cached)| cached.used)
+        .map(|(url, _cached)| url.clone())
+      else {
+        break;
+      };
+      info!("evicting {}", url);
+      self.remove(&url);
+      self.stats.evictions += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NOW: i64 = 1_000_000;
+
+  // what load does for a file the server sends
+  fn load(cache: &mut Cache, url: &str, content: &str) {
+    if let Lookup::Missing = cache.lookup(url, "v1", NOW) {
+      let validators = Validators {
+        etag: Some(format!("\"{}\"", url)),
+        last_modified: None,
+      };
+      cache.insert(url, "v1".to_string(), content, validators, NOW);
+    }
+  }
+
+  fn fresh(cache: &mut Cache, url: &str, version: &str, now: i64) -> Option<String> {
+    match cache.lookup(url, version, now) {
+      Lookup::Fresh(content) => Some(content),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn fresh_content_is_served_from_memory() {
+    let mut cache = Cache::default();
+    load(&mut cache, "a", "content");
+    assert_eq!(fresh(&mut cache, "a", "v1", NOW + FRESH_SECS - 1).as_deref(), Some("content"));
+    assert_eq!((cache.stats.hits, cache.stats.misses, cache.stats.bytes), (1, 1, 7));
+  }
+
+  #[test]
+  fn other_versions_miss() {
+    let mut cache = Cache::default();
+    load(&mut cache, "a", "content");
+    assert!(matches!(cache.lookup("a", "v2", NOW), Lookup::Missing));
+    // the old content is dropped right away
+    assert!(cache.files.is_empty());
+    assert_eq!(cache.stats.bytes, 0);
+  }
+
+  #[test]
+  fn least_recently_used_is_evicted() {
+    let mut cache = Cache::default();
+    let third = "x".repeat(MAX_BYTES / 3);
+    load(&mut cache, "a", &third);
+    load(&mut cache, "b", &third);
+    load(&mut cache, "c", &third);
+    // touching a makes b the oldest
+    assert!(fresh(&mut cache, "a", "v1", NOW).is_some());
+    load(&mut cache, "d", &third);
+    let mut urls: Vec<&str> = cache.files.keys().map(String::as_str).collect();
+    urls.sort();
+    assert_eq!(urls, ["a", "c", "d"]);
+    assert_eq!(cache.stats.evictions, 1);
+    assert_eq!(cache.stats.bytes, 3 * third.len());
+  }
+
+  #[test]
+  fn content_larger_than_the_cache_isnt_kept() {
+    let mut cache = Cache::default();
+    load(&mut cache, "a", "content");
+    load(&mut cache, "big", &"x".repeat(MAX_BYTES + 1));
+    assert!(cache.files.contains_key("a"));
+    assert!(!cache.files.contains_key("big"));
+    assert_eq!(cache.stats.bytes, 7);
+  }
+
+  #[test]
+  fn stale_content_is_revalidated() {
+    let mut cache = Cache::default();
+    load(&mut cache, "a", "content");
+    let later = NOW + FRESH_SECS;
+    let Lookup::Stale(validators) = cache.lookup("a", "v1", later) else {
+      panic!("content should be stale");
+    };
+    assert_eq!(validators.etag.as_deref(), Some("\"a\""));
+    assert_eq!(cache.revalidated("a", later).as_deref(), Some("content"));
+    // a 304 counts as a fresh check
+    assert_eq!(fresh(&mut cache, "a", "v1", later + 1).as_deref(), Some("content"));
+    assert_eq!(cache.stats.revalidated, 1);
+  }
+
+  #[test]
+  fn revalidation_misses_evicted_content() {
+    let mut cache = Cache::default();
+    let half = "x".repeat(MAX_BYTES / 2);
+    load(&mut cache, "a", &half);
+    assert!(matches!(cache.lookup("a", "v1", NOW + FRESH_SECS), Lookup::Stale(_)));
+    // other loads evict it while the request is running
+    load(&mut cache, "b", &half);
+    load(&mut cache, "c", &half);
+    assert_eq!(cache.revalidated("a", NOW + FRESH_SECS), None);
+    assert_eq!(cache.stats.revalidated, 0);
+  }
+}
@@ -1,19 +1,19 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Mutex;
 
-use ansi_term::Colour;
-use clap::{ArgAction, Parser};
+use ansi_term::{Colour, Style};
+use clap::{ArgAction, Parser, Subcommand};
 use lazy_static::lazy_static;
 use log::info;
 use macros::{cmds_init, shell_cmd};
+use similar::{ChangeTag, DiffOp, TextDiff};
 
 use crate::app::App;
 use crate::less::Less;
+use crate::search::Search;
 use crate::termstate::TermState;
-use crate::{clear, consts, filesystem, utils, write, write_buf, writeln, writeln_buf};
-
-const DIR_PREFIX: &str = "dr-xr-xr-x\t2 root\troot";
-const FILE_PREFIX: &str = "-r--r--r--\t1 root\troot";
+use crate::vi::Vi;
+use crate::{cache, clear, consts, ewriteln, filesystem, highlight, overlay, pending, utils, write, write_buf, writeln, writeln_buf};
 
 #[macro_export]
 macro_rules! new {
@@ -42,8 +42,25 @@ macro_rules! init {
 #[macro_export]
 macro_rules! write_solo {
   ($state:expr, $out:expr) => {{
-    new!($state);
-    write_buf!("{}", $out);
+    write_stream!($state, utils::Stream::Stdout, $out);
+  }};
+}
+
+#[macro_export]
+macro_rules! write_error {
+  ($state:expr, $out:expr) => {{
+    write_stream!($state, utils::Stream::Stderr, $out);
+  }};
+}
+
+#[macro_export]
+macro_rules! write_stream {
+  ($state:expr, $stream:expr, $out:expr) => {{
+    let out = format!("{}", $out);
+    if !utils::write_captured($stream, &(out.clone() + consts::NEWLINE)) {
+      new!($state);
+      write_buf!("{}", out);
+    }
     init!($state);
   }};
 }
@@ -57,7 +74,7 @@ macro_rules! parse_args {
         let error_str = error.to_string();
         let lines: Vec<&str> = error_str.lines().collect();
         $state.cursor_y += lines.len() + 2;
-        write_solo!($state, lines.join(consts::NEWLINE));
+        write_error!($state, lines.join(consts::NEWLINE));
         return $ret;
       }
     }
@@ -79,20 +96,38 @@ struct LsArgs {
   help: Option<bool>,
   #[arg(short, action, help = "long format")]
   list: bool,
+  #[arg(long, help = "only list posts tagged TAG")]
+  tag: Option<String>,
 }
 
 #[derive(Parser)]
 #[command(about = "change directory")]
 struct CdArgs {
-  #[arg(help = "directory to change into")]
+  #[arg(help = "directory to change into, defaults to ~")]
+  dir: Option<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "push directory onto the directory stack")]
+struct PushdArgs {
+  #[arg(help = "directory to change into, swaps the top two directories if omitted")]
   dir: Option<String>,
 }
 
+#[derive(Parser)]
+#[command(about = "display directory stack")]
+struct DirsArgs {
+  #[arg(short, action, help = "print one entry per line, prefixed with its index")]
+  verbose: bool,
+}
+
 #[derive(Parser)]
 #[command(about = "print file to stdout")]
 struct CatArgs {
-  #[arg(help = "file to print")]
-  file: String,
+  #[arg(help = "file to print, reads stdin if omitted")]
+  file: Option<String>,
+  #[arg(short = 'R', long, action, help = "don't highlight source files")]
+  plain: bool,
 }
 
 #[derive(Parser)]
@@ -100,6 +135,125 @@ struct CatArgs {
 struct LessArgs {
   #[arg(help = "file to view")]
   file: String,
+  #[arg(short = 'R', long, action, help = "don't highlight source files")]
+  plain: bool,
+}
+
+#[derive(Parser)]
+#[command(about = "edit file, site files are read-only")]
+struct ViArgs {
+  #[arg(help = "file to edit")]
+  file: Option<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "inspect the file content cache")]
+struct CacheArgs {
+  #[command(subcommand)]
+  action: CacheAction,
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+  #[command(about = "print cache usage and hit counts")]
+  Stats,
+  #[command(about = "drop all cached files")]
+  Clear,
+}
+
+#[derive(Parser)]
+#[command(about = "print file checksums")]
+struct SumArgs {
+  #[arg(required = true, help = "files to hash")]
+  files: Vec<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "search the content of all files")]
+struct SearchArgs {
+  #[arg(required = true, help = "words to look for, typos are tolerated")]
+  query: Vec<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "print resolved symbolic links")]
+struct ReadlinkArgs {
+  #[arg(short, action, help = "canonicalize by following every link recursively")]
+  canonicalize: bool,
+  #[arg(help = "link to read")]
+  file: String,
+}
+
+#[derive(Parser)]
+#[command(about = "search for files in a directory hierarchy")]
+struct FindArgs {
+  #[arg(help = "directory to search, defaults to .")]
+  dir: Option<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "display file status")]
+struct StatArgs {
+  #[arg(help = "file to inspect")]
+  file: String,
+}
+
+#[derive(Parser)]
+#[command(about = "make directories")]
+struct MkdirArgs {
+  #[arg(short, action, help = "make parent directories as needed, no error if existing")]
+  parents: bool,
+  #[arg(required = true, help = "directories to create")]
+  dirs: Vec<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "create empty files or update their timestamps")]
+struct TouchArgs {
+  #[arg(required = true, help = "files to touch")]
+  files: Vec<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "remove files or directories")]
+struct RmArgs {
+  #[arg(short, action, help = "remove directories and their contents recursively")]
+  recursive: bool,
+  #[arg(short, action, help = "ignore nonexistent files")]
+  force: bool,
+  #[arg(required = true, help = "files to remove")]
+  files: Vec<String>,
+}
+
+#[derive(Parser)]
+#[command(about = "copy files and directories")]
+struct CpArgs {
+  #[arg(short, action, help = "copy directories recursively")]
+  recursive: bool,
+  #[arg(help = "file to copy")]
+  source: String,
+  #[arg(help = "destination file or directory")]
+  dest: String,
+}
+
+#[derive(Parser)]
+#[command(about = "move files and directories")]
+struct MvArgs {
+  #[arg(help = "file to move")]
+  source: String,
+  #[arg(help = "destination file or directory")]
+  dest: String,
+}
+
+#[derive(Parser)]
+#[command(about = "compare files line by line")]
+struct DiffArgs {
+  #[arg(short, action, help = "output 3 lines of unified context")]
+  unified: bool,
+  #[arg(help = "original file")]
+  file_a: String,
+  #[arg(help = "changed file")]
+  file_b: String,
 }
 
 type CommandFn = fn(&mut TermState, &str) -> Option<Box<dyn App>>;
@@ -159,72 +313,646 @@ pub fn echo(state: &mut TermState, args: &str) -> Option<Box<dyn App>> {
   None
 }
 
-#[shell_cmd(COMMANDS, "cat\tFILE\tprint file to stdout")]
+#[shell_cmd(COMMANDS, "cat\t[FILE]\tprint file (or stdin) to stdout")]
 pub fn cat(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
   let args: CatArgs = parse_args!(state, CatArgs::try_parse_from(cmdline.split(" ")), None);
-  let path_str = args.file;
+  let Some(path_str) = args.file else {
+    match state.stdin.take() {
+      Some(content) => write_source(state, "", &content, args.plain),
+      None => write_error!(state, "cat: missing file operand"),
+    }
+    return None;
+  };
   let path = state.path.join(path_str.clone());
   info!("{}", path);
   let resolved = utils::resolve_path(&path);
   info!("{}", resolved);
-  let change = filesystem::ROOT.get_file(&resolved);
-  if change.is_ok() {
-    let file = change.unwrap();
-    if file.is_dir {
-      write_solo!(state, format!("read error: {} Is a directory", path_str));
+  let file = match filesystem::get_file(&resolved) {
+    Ok(file) => file,
+    Err(_) => {
+      write_error!(state, format!("{}: No such file", path_str));
       return None;
     }
-    info!("{}", file.url);
-    let content = file.load().unwrap();
-    let lines: Vec<&str> = content.lines().collect();
-    state.cursor_y += lines.len() + 2;
-    state.cursor_x = consts::PREFIX.len();
-    new!(state);
-    for line in lines {
-      writeln!(state, "{}", line);
+  };
+  if file.is_dir {
+    write_error!(state, format!("read error: {} Is a directory", path_str));
+    return None;
+  }
+  info!("{}", file.url);
+  let filename = file.filename.to_string();
+  pending::spawn(state, async move { file.load().await }, move |state, content| {
+    match content {
+      Ok(content) => write_source(state, &filename, &content, args.plain),
+      Err(error) => write_error!(state, format!("cat: {}", error)),
     }
-    prefix!(state);
+    None
+  })
+}
+
+// source files are highlighted unless the output goes into a file
+fn write_source(state: &mut TermState, filename: &str, content: &str, plain: bool) {
+  let highlighted = if plain || utils::is_captured(utils::Stream::Stdout) {
+    None
   } else {
-    write_solo!(state, format!("{}: No such file", path_str));
+    highlight::highlight(filename, content)
+  };
+  write_content(state, highlighted.as_deref().unwrap_or(content));
+}
+
+fn write_content(state: &mut TermState, content: &str) {
+  let lines: Vec<&str> = content.lines().collect();
+  state.cursor_y += lines.len() + 2;
+  state.cursor_x = consts::PREFIX.len();
+  new!(state);
+  for line in lines {
+    writeln!(state, "{}", line);
   }
-  None
+  prefix!(state);
 }
 
-#[shell_cmd(COMMANDS, "less\tFILE\tview file in screen", cmd_type=CmdType::MobileArg)]
-pub fn less(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
-  let args: LessArgs = parse_args!(state, LessArgs::try_parse_from(cmdline.split(" ")), None);
-  let mut less = Less::new();
-  match less.less(state, &args.file) {
-    Ok(()) => {
-      let app_box: Box<dyn App> = Box::new(less);
-      Some(app_box)
+#[shell_cmd(COMMANDS, "find\t[DIR]\tlist files recursively")]
+pub fn find(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: FindArgs = parse_args!(state, FindArgs::try_parse_from(cmdline.split(" ")), None);
+  let path_str = args.dir.unwrap_or(".".to_string());
+  let path = state.path.join(path_str.clone());
+  let resolved = utils::resolve_path(&path);
+  info!("{}", resolved);
+  pending::spawn(state, filesystem::prefetch_tree(resolved.clone()), move |state, ()| {
+    match filesystem::get_file(&resolved) {
+      Ok(dir) => {
+        let mut paths = vec![path_str.clone()];
+        find_rec(&dir, path_str.trim_end_matches('/'), &mut paths);
+        new!(state);
+        for path in paths {
+          writeln!(state, "{}", path);
+        }
+        prefix!(state);
+      }
+      Err(_) => write_error!(state, format!("find: {}: No such file or directory", path_str)),
     }
+    None
+  })
+}
+
+fn find_rec(dir: &filesystem::Node, path: &str, paths: &mut Vec<String>) {
+  let mut children = dir.children();
+  children.sort_by(|(name, _entry), (other, _other_entry)| name.cmp(other));
+  for (name, entry) in children {
+    let child_path = format!("{}/{}", path, name);
+    paths.push(child_path.clone());
+    if entry.is_dir {
+      find_rec(&entry, &child_path, paths);
+    }
+  }
+}
+
+#[shell_cmd(COMMANDS, "stat\tFILE\tdisplay file status")]
+pub fn stat(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: StatArgs = parse_args!(state, StatArgs::try_parse_from(cmdline.split(" ")), None);
+  let path = state.path.join(args.file.clone());
+  let resolved = utils::resolve_path(&path);
+  info!("{}", resolved);
+  let file = match filesystem::get_file_nofollow(&resolved) {
+    Ok(file) => file,
     Err(error) => {
-      write_solo!(state, error);
+      write_error!(state, format!("stat: {}: {}", args.file, error));
+      return None;
+    }
+  };
+  let (name, kind) = match file.link {
+    Some(target) => (format!("{} -> {}", args.file, target), "symbolic link"),
+    None if file.is_dir => (args.file.clone(), "directory"),
+    None => (args.file.clone(), "regular file"),
+  };
+  let modified = chrono::DateTime::<chrono::Utc>::from_timestamp(file.modified as i64, 0)
+    .unwrap()
+    .format("%Y-%m-%d %H:%M:%S");
+  let created = chrono::DateTime::<chrono::Utc>::from_timestamp(file.created as i64, 0)
+    .unwrap()
+    .format("%Y-%m-%d %H:%M:%S");
+  new!(state);
+  writeln!(state, "  File: {}", name);
+  writeln!(state, "  Size: {}\tType: {}", file.size, kind);
+  writeln!(
+    state,
+    "Access: ({:04o}/{})  Uid: {}  Gid: {}",
+    file.mode,
+    file.get_mode_str(),
+    file.owner,
+    file.group
+  );
+  writeln!(state, " Links: {}", file.nlink);
+  writeln!(state, "Modify: {}", modified);
+  writeln!(state, " Birth: {}", created);
+  if let Some(meta) = &file.meta {
+    if let Some(title) = meta.title {
+      writeln!(state, " Title: {}", title);
+    }
+    if let Some(date) = meta.date.and_then(|date| chrono::DateTime::<chrono::Utc>::from_timestamp(date as i64, 0)) {
+      writeln!(state, "  Date: {}", date.format("%Y-%m-%d %H:%M:%S"));
+    }
+    if !meta.tags.is_empty() {
+      writeln!(state, "  Tags: {}", meta.tags.join(", "));
+    }
+    if let Some(summary) = meta.summary {
+      writeln!(state, "Summary: {}", summary);
+    }
+    if meta.draft {
+      writeln!(state, " Draft: yes");
+    }
+  }
+  prefix!(state);
+  None
+}
+
+#[shell_cmd(COMMANDS, "mkdir\t[-p] DIR\tcreate directories")]
+pub fn mkdir(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: MkdirArgs = parse_args!(state, MkdirArgs::try_parse_from(cmdline.split(" ")), None);
+  let mut errors = vec![];
+  for dir in args.dirs {
+    let resolved = utils::resolve_path(state.path.join(dir.clone()));
+    let result = if args.parents {
+      let files = utils::resolve_path_files(&resolved);
+      (1..=files.len())
+        .map(|depth| files[..depth].join("/"))
+        .filter(|path| !filesystem::get_file(path).is_ok_and(|file| file.is_dir))
+        .try_for_each(|path| overlay::mkdir(&path))
+    } else {
+      overlay::mkdir(&resolved)
+    };
+    if let Err(error) = result {
+      errors.push(format!("mkdir: can't create directory '{}': {}", dir, error));
+    }
+  }
+  write_errors(state, errors);
+  None
+}
+
+#[shell_cmd(COMMANDS, "touch\tFILE\tcreate empty files")]
+pub fn touch(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: TouchArgs = parse_args!(state, TouchArgs::try_parse_from(cmdline.split(" ")), None);
+  let mut errors = vec![];
+  for file in args.files {
+    let resolved = utils::resolve_path(state.path.join(file.clone()));
+    if let Err(error) = overlay::touch(&resolved) {
+      errors.push(format!("touch: {}: {}", file, error));
+    }
+  }
+  write_errors(state, errors);
+  None
+}
+
+#[shell_cmd(COMMANDS, "rm\t[-rf] FILE\tremove files")]
+pub fn rm(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: RmArgs = parse_args!(state, RmArgs::try_parse_from(cmdline.split(" ")), None);
+  let mut errors = vec![];
+  for file in args.files {
+    let resolved = utils::resolve_path(state.path.join(file.clone()));
+    match filesystem::get_file_nofollow(&resolved) {
+      _ if resolved.is_empty() => errors.push("rm: refusing to remove '/'".to_string()),
+      Ok(entry) if entry.is_dir && !args.recursive => {
+        errors.push(format!("rm: '{}' is a directory", file))
+      }
+      Ok(entry) => overlay::remove(&entry.url),
+      Err(_) if args.force => {}
+      Err(_) => errors.push(format!("rm: {}: No such file or directory", file)),
+    }
+  }
+  write_errors(state, errors);
+  None
+}
+
+#[shell_cmd(COMMANDS, "cp\t[-r] SRC DST\tcopy files")]
+pub fn cp(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: CpArgs = parse_args!(state, CpArgs::try_parse_from(cmdline.split(" ")), None);
+  let result = copy_target(state, &args.source, &args.dest).and_then(|(source, dest)| {
+    if source.is_dir && !args.recursive {
+      return Err(format!("omitting directory '{}'", args.source));
+    }
+    overlay::copy(&source, &dest)
+  });
+  write_errors(state, result.err().map(|error| format!("cp: {}", error)).into_iter().collect());
+  None
+}
+
+#[shell_cmd(COMMANDS, "mv\tSRC DST\tmove files")]
+pub fn mv(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: MvArgs = parse_args!(state, MvArgs::try_parse_from(cmdline.split(" ")), None);
+  let result = copy_target(state, &args.source, &args.dest).and_then(|(source, dest)| {
+    overlay::copy(&source, &dest)?;
+    overlay::remove(&source.url);
+    Ok(())
+  });
+  write_errors(state, result.err().map(|error| format!("mv: {}", error)).into_iter().collect());
+  None
+}
+
+#[shell_cmd(COMMANDS, "cache\tstats|clear\tinspect file content cache")]
+pub fn cache(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: CacheArgs = parse_args!(state, CacheArgs::try_parse_from(cmdline.split(" ")), None);
+  match args.action {
+    CacheAction::Stats => {
+      let stats = cache::stats();
+      new!(state);
+      writeln!(state, "entries:     {}", stats.entries);
+      writeln!(
+        state,
+        "size:        {}/{}",
+        utils::human_size(stats.bytes as u64),
+        utils::human_size(cache::MAX_BYTES as u64)
+      );
+      writeln!(state, "hits:        {}", stats.hits);
+      writeln!(state, "revalidated: {}", stats.revalidated);
+      writeln!(state, "misses:      {}", stats.misses);
+      writeln!(state, "evictions:   {}", stats.evictions);
+      prefix!(state);
+    }
+    CacheAction::Clear => {
+      cache::clear();
+      init!(state);
+    }
+  }
+  None
+}
+
+#[shell_cmd(COMMANDS, "reset-fs\t\tdiscard all file changes", name="reset-fs")]
+pub fn reset_fs(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
+  overlay::reset();
+  // directories of the overlay are gone now
+  let is_lower = |dir: &filesystem::Node| matches!(dir, filesystem::Node::Lower(_));
+  state.dir_stack.retain(is_lower);
+  state.oldpwd = state.oldpwd.take().filter(is_lower);
+  if !is_lower(&state.path) {
+    change_dir(state, filesystem::root());
+  }
+  init!(state);
+  None
+}
+
+// source node and destination path of cp and mv, copying into directories keeps the name
+fn copy_target(
+  state: &TermState,
+  source_str: &str,
+  dest_str: &str,
+) -> Result<(filesystem::Node, String), String> {
+  let source_path = utils::resolve_path(state.path.join(source_str));
+  if source_path.is_empty() {
+    return Err("refusing to copy '/'".to_string());
+  }
+  let source = filesystem::get_file(&source_path)
+    .map_err(|_| format!("{}: No such file or directory", source_str))?;
+  let dest = utils::resolve_path(state.path.join(dest_str));
+  match filesystem::get_file(&dest) {
+    Ok(dir) if dir.is_dir => Ok((source.clone(), dir.child_path(&source.filename))),
+    _ => Ok((source, dest)),
+  }
+}
+
+// prints lines between two prompts, or just a new prompt without any
+fn write_errors(state: &mut TermState, lines: Vec<String>) {
+  if lines.is_empty() {
+    init!(state);
+    return;
+  }
+  new!(state);
+  for line in lines {
+    ewriteln!(state, "{}", line);
+  }
+  prefix!(state);
+}
+
+#[shell_cmd(COMMANDS, "readlink\t[-f] FILE\tprint link target")]
+pub fn readlink(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: ReadlinkArgs = parse_args!(state, ReadlinkArgs::try_parse_from(cmdline.split(" ")), None);
+  let path = state.path.join(args.file.clone());
+  let resolved = utils::resolve_path(&path);
+  info!("{}", resolved);
+  let target = if args.canonicalize {
+    filesystem::get_file(&resolved)
+      .map(|file| "/".to_string() + &file.url)
+  } else {
+    filesystem::get_file_nofollow(&resolved)
+      .map(|file| file.link.unwrap_or("").to_string())
+  };
+  match target {
+    // like readlink, regular files print nothing
+    Ok(target) if target.is_empty() => init!(state),
+    Ok(target) => write_solo!(state, target),
+    Err(error) => write_error!(state, format!("readlink: {}: {}", args.file, error)),
+  }
+  None
+}
+
+#[shell_cmd(COMMANDS, "sha256sum\tFILE...\tprint SHA-256 checksums")]
+pub fn sha256sum(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  checksums(state, cmdline, utils::sha256_hex, true)
+}
+
+#[shell_cmd(COMMANDS, "md5sum\tFILE...\tprint MD5 checksums")]
+pub fn md5sum(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  checksums(state, cmdline, utils::md5_hex, false)
+}
+
+// digests of the content cat shows, the SHA-256 from the build is of the served file, which is the same
+// unless load cuts off front matter, everything else is hashed here
+fn checksums(
+  state: &mut TermState,
+  cmdline: &str,
+  digest: fn(&str) -> String,
+  embedded: bool,
+) -> Option<Box<dyn App>> {
+  let args: SumArgs = parse_args!(state, SumArgs::try_parse_from(cmdline.split(" ")), None);
+  let cmd = cmdline.split(" ").next().unwrap_or_default().to_string();
+  let files: Vec<(String, Result<filesystem::Node, String>)> = args
+    .files
+    .into_iter()
+    .map(|path_str| {
+      let file = find_file(state, &path_str);
+      (path_str, file)
+    })
+    .collect();
+  pending::spawn(
+    state,
+    async move {
+      let mut lines = vec![];
+      for (path_str, file) in files {
+        let sum = match file {
+          Ok(file) => match file.sha256.filter(|_sha256| embedded && file.meta.is_none() && file.is_readable()) {
+            Some(sha256) => Ok(sha256.to_string()),
+            None => file.load().await.map(|content| digest(&content)),
+          },
+          Err(error) => Err(error),
+        };
+        lines.push(
+          sum
+            .map(|sum| format!("{}  {}", sum, path_str))
+            .map_err(|error| format!("{}: {}", cmd, error)),
+        );
+      }
+      lines
+    },
+    |state, lines| {
+      new!(state);
+      for line in lines {
+        match line {
+          Ok(line) => writeln!(state, "{}", line),
+          Err(error) => ewriteln!(state, "{}", error),
+        }
+      }
+      prefix!(state);
       None
+    },
+  )
+}
+
+#[shell_cmd(COMMANDS, "posts\t\tlist posts by date")]
+pub fn posts(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
+  pending::spawn(state, filesystem::prefetch_tree(String::new()), |state, ()| {
+    let mut posts = vec![];
+    posts_rec(&filesystem::root(), &mut posts);
+    posts.sort_by(|a, b| b.date().cmp(&a.date()).then(a.url.cmp(&b.url)));
+    new!(state);
+    for post in posts {
+      let date = chrono::DateTime::<chrono::Utc>::from_timestamp(post.date() as i64, 0).unwrap_or_default();
+      let title = post.meta.as_ref().and_then(|meta| meta.title).unwrap_or_default();
+      // only built with SITE_DRAFTS
+      let draft = if post.meta.as_ref().is_some_and(|meta| meta.draft) {
+        Colour::Yellow.paint(" (draft)").to_string()
+      } else {
+        String::new()
+      };
+      writeln!(
+        state,
+        "{}  {}  {}{}",
+        date.format("%Y-%m-%d"),
+        Colour::Blue.bold().paint(format!("/{}", post.url)),
+        title,
+        draft
+      );
+    }
+    prefix!(state);
+    None
+  })
+}
+
+#[shell_cmd(COMMANDS, "tags\t\tlist the tags of all posts")]
+pub fn tags(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
+  pending::spawn(state, filesystem::prefetch_tree(String::new()), |state, ()| {
+    let mut posts = vec![];
+    posts_rec(&filesystem::root(), &mut posts);
+    let mut tags: BTreeMap<&str, usize> = BTreeMap::new();
+    for post in &posts {
+      for tag in post.meta.iter().flat_map(|meta| meta.tags) {
+        *tags.entry(tag).or_default() += 1;
+      }
+    }
+    new!(state);
+    for (tag, count) in tags {
+      writeln!(state, "{}\t{}", tag, count);
+    }
+    prefix!(state);
+    None
+  })
+}
+
+// files with front matter, the ones unioned into other directories only once
+fn posts_rec(dir: &filesystem::Node, posts: &mut Vec<filesystem::Node>) {
+  for (_name, entry) in dir.children() {
+    if entry.is_dir && entry.link.is_none() {
+      posts_rec(&entry, posts);
+    } else if entry.meta.is_some() && !posts.iter().any(|post| post.url == entry.url) {
+      posts.push(entry);
     }
   }
 }
 
+#[shell_cmd(COMMANDS, "search\tQUERY\tsearch the content of all files")]
+pub fn search(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: SearchArgs = parse_args!(state, SearchArgs::try_parse_from(cmdline.split(" ")), None);
+  let query: Vec<String> = args.query.into_iter().filter(|word| !word.is_empty()).collect();
+  Search::open(state, query.join(" "))
+}
+
+#[shell_cmd(COMMANDS, "diff\t[-u] A B\tcompare two files")]
+pub fn diff(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: DiffArgs = parse_args!(state, DiffArgs::try_parse_from(cmdline.split(" ")), None);
+  let (file_a, file_b) = match (find_file(state, &args.file_a), find_file(state, &args.file_b)) {
+    (Ok(file_a), Ok(file_b)) => (file_a, file_b),
+    (Err(error), _) | (_, Err(error)) => {
+      write_error!(state, format!("diff: {}", error));
+      return None;
+    }
+  };
+  pending::spawn(
+    state,
+    async move { (file_a.load().await, file_b.load().await) },
+    move |state, contents| {
+      let (old, new) = match contents {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(error), _) | (_, Err(error)) => {
+          write_error!(state, format!("diff: {}", error));
+          return None;
+        }
+      };
+      let text_diff = TextDiff::from_lines(&old, &new);
+      let lines = if args.unified {
+        unified_diff(&text_diff, &args.file_a, &args.file_b)
+      } else {
+        normal_diff(&text_diff)
+      };
+      // identical files produce no output
+      if lines.is_empty() {
+        init!(state);
+        return None;
+      }
+      new!(state);
+      for line in lines {
+        writeln!(state, "{}", line);
+      }
+      prefix!(state);
+      None
+    },
+  )
+}
+
+fn find_file(state: &TermState, path_str: &str) -> Result<filesystem::Node, String> {
+  let path = state.path.join(path_str);
+  let resolved = utils::resolve_path(&path);
+  info!("{}", resolved);
+  let file = filesystem::get_file(&resolved)
+    .map_err(|_| format!("{}: No such file", path_str))?;
+  if file.is_dir {
+    return Err(format!("read error: {} Is a directory", path_str));
+  }
+  Ok(file)
+}
+
+fn diff_range(start: usize, len: usize) -> String {
+  match len {
+    0 => format!("{}", start),
+    1 => format!("{}", start + 1),
+    _ => format!("{},{}", start + 1, start + len),
+  }
+}
+
+fn diff_lines(lines: &[&str], marker: &str, colour: Colour) -> Vec<String> {
+  lines
+    .iter()
+    .map(|line| colour.paint(format!("{}{}", marker, line.trim_end_matches(['\n', '\r']))).to_string())
+    .collect()
+}
+
+fn normal_diff(text_diff: &TextDiff<str>) -> Vec<String> {
+  let old_lines = text_diff.old_slices();
+  let new_lines = text_diff.new_slices();
+  let mut out = Vec::new();
+  for op in text_diff.ops() {
+    let (old_range, new_range) = (op.old_range(), op.new_range());
+    let command = match op {
+      DiffOp::Equal { .. } => continue,
+      DiffOp::Delete { .. } => 'd',
+      DiffOp::Insert { .. } => 'a',
+      DiffOp::Replace { .. } => 'c',
+    };
+    out.push(
+      Colour::Cyan
+        .paint(format!(
+          "{}{}{}",
+          diff_range(old_range.start, old_range.len()),
+          command,
+          diff_range(new_range.start, new_range.len())
+        ))
+        .to_string(),
+    );
+    out.extend(diff_lines(&old_lines[old_range.clone()], "< ", Colour::Red));
+    if command == 'c' {
+      out.push("---".to_string());
+    }
+    out.extend(diff_lines(&new_lines[new_range.clone()], "> ", Colour::Green));
+  }
+  out
+}
+
+fn unified_diff<'a>(text_diff: &'a TextDiff<'a, 'a, 'a, str>, file_a: &str, file_b: &str) -> Vec<String> {
+  let mut out = Vec::new();
+  for hunk in text_diff.unified_diff().context_radius(3).iter_hunks() {
+    if out.is_empty() {
+      out.push(Style::new().bold().paint(format!("--- {}", file_a)).to_string());
+      out.push(Style::new().bold().paint(format!("+++ {}", file_b)).to_string());
+    }
+    out.push(Colour::Cyan.paint(hunk.header().to_string()).to_string());
+    for change in hunk.iter_changes() {
+      let line = [change.value()];
+      out.extend(match change.tag() {
+        ChangeTag::Delete => diff_lines(&line, "-", Colour::Red),
+        ChangeTag::Insert => diff_lines(&line, "+", Colour::Green),
+        ChangeTag::Equal => vec![format!(" {}", line[0].trim_end_matches(['\n', '\r']))],
+      });
+    }
+  }
+  out
+}
+
+#[shell_cmd(COMMANDS, "less\tFILE\tview file in screen", cmd_type=CmdType::MobileArg)]
+pub fn less(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: LessArgs = parse_args!(state, LessArgs::try_parse_from(cmdline.split(" ")), None);
+  Less::open_at(state, &args.file, 0, !args.plain, |state, error| write_error!(state, error))
+}
+
+#[shell_cmd(COMMANDS, "vi\t[FILE]\tedit file in screen")]
+pub fn vi(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: ViArgs = parse_args!(state, ViArgs::try_parse_from(cmdline.split(" ")), None);
+  Vi::edit(state, &args.file.unwrap_or_default())
+}
+
+#[shell_cmd(COMMANDS, "vim\t[FILE]\tedit file in screen", cmd_type=CmdType::Alias)]
+pub fn vim(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  vi(state, cmdline)
+}
+
 #[shell_cmd(COMMANDS, "ll\t[PATH]\tlist directory in long format", cmd_type=CmdType::Alias)]
 pub fn ll(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
-  let out = ls_rec(state, &format!("ls -lh{}", cmdline.trim_start_matches("ll")));
-  write!("{}", out);
-  if !out.is_empty() {
-    prefix!(state);
-  }
-  None
+  list(state, format!("ls -lh{}", cmdline.trim_start_matches("ll")))
 }
 
 #[shell_cmd(COMMANDS, "ls\t[PATH]\tlist directory contents")]
 pub fn ls(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
-  let out = ls_rec(state, cmdline);
-  write!("{}", out);
+  list(state, cmdline.to_string())
+}
+
+// recursive listings need the manifests of the whole subtree first
+fn list(state: &mut TermState, cmdline: String) -> Option<Box<dyn App>> {
+  let tree = LsArgs::try_parse_from(cmdline.split(" "))
+    .ok()
+    .filter(|args| args.recursive)
+    .map(|args| utils::resolve_path(state.path.join(args.file.unwrap_or(".".to_string()))));
+  pending::spawn(
+    state,
+    async move {
+      if let Some(path) = tree {
+        filesystem::prefetch_tree(path).await;
+      }
+    },
+    move |state, ()| {
+      let out = ls_rec(state, &cmdline);
+      write_listing(state, out);
+      None
+    },
+  )
+}
+
+fn write_listing(state: &mut TermState, out: String) {
   // only is empty if error was encountered
-  if !out.is_empty() {
+  if out.is_empty() {
+    return;
+  }
+  // the leading newline only moves past the prompt
+  if utils::write_captured(utils::Stream::Stdout, out.trim_start_matches(consts::NEWLINE)) {
+    init!(state);
+  } else {
+    write!("{}", out);
     prefix!(state);
   }
-  None
 }
 
 pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
@@ -237,11 +965,16 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
   let path = state.path.join(path_str.clone());
   let resolved = utils::resolve_path(&path);
   info!("{}", resolved);
-  let change = filesystem::ROOT.get_file(&resolved);
-  if resolved.is_empty() || change.is_ok() {
-    return if !lsargs.directory && (resolved.is_empty() || change.clone().unwrap().is_dir) {
+  let change = filesystem::get_file(&resolved);
+  // long listings show the link itself instead of its target
+  let link = filesystem::get_file_nofollow(&resolved)
+    .ok()
+    .filter(|entry| entry.link.is_some());
+  let list_link = lsargs.list && link.is_some();
+  if resolved.is_empty() || change.is_ok() || list_link {
+    return if !lsargs.directory && !list_link && (resolved.is_empty() || change.clone().unwrap().is_dir) {
       let dir = if resolved.is_empty() {
-        &filesystem::ROOT
+        filesystem::root()
       } else {
         change.unwrap()
       };
@@ -253,18 +986,42 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
       let mut totalsize = 0;
       let mut entries: Vec<String> = Vec::new();
       let mut recursive_dirs: Vec<String> = Vec::new();
-      for (name, entry) in &dir.entries {
-        if entry.is_dir {
+      for (name, entry) in dir.children() {
+        // tagged listings only show the matching posts, but still descend
+        if lsargs.tag.as_ref().is_some_and(|tag| !entry.has_tag(tag)) {
+          if lsargs.recursive && entry.is_dir && entry.link.is_none() {
+            recursive_dirs.push(name.to_string());
+          }
+          continue;
+        }
+        if let Some(target) = entry.link {
+          let formatted_name = Colour::Cyan.bold().paint(name).to_string();
+          if lsargs.list {
+            state.cursor_y += 1;
+            totalsize += entry.size;
+            entries.push(format!(
+              "{}\t{}\t{} {} -> {}{}",
+              entry.get_long_prefix(),
+              entry.get_size(lsargs.human),
+              entry.get_date_str(),
+              formatted_name,
+              target,
+              consts::NEWLINE
+            ));
+          } else {
+            entries.push(formatted_name);
+          }
+        } else if entry.is_dir {
           if lsargs.recursive {
             recursive_dirs.push(name.to_string());
           }
-          let formatted_name = Colour::Blue.bold().paint(*name).to_string();
+          let formatted_name = Colour::Blue.bold().paint(name).to_string();
           if lsargs.list {
             state.cursor_y += 1;
             totalsize += entry.size;
             entries.push(format!(
               "{}\t{}\t{} {}{}",
-              DIR_PREFIX,
+              entry.get_long_prefix(),
               entry.get_size(lsargs.human),
               entry.get_date_str(),
               formatted_name,
@@ -279,7 +1036,7 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
             totalsize += entry.size;
             entries.push(format!(
               "{}\t{}\t{} {}{}",
-              FILE_PREFIX,
+              entry.get_long_prefix(),
               entry.get_size(lsargs.human),
               entry.get_date_str(),
               name,
@@ -303,6 +1060,9 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
           if lsargs.human {
             options += "h"
           }
+          if let Some(tag) = &lsargs.tag {
+            options += &format!(" --tag {}", tag);
+          }
           let file = &format!("{}/{}", path_str, entry);
           let out = ls_rec(state, &format!("ls {} {}", options, file));
           entries.push(out);
@@ -326,13 +1086,17 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
         format!("{}{}{}", consts::NEWLINE, prefix, &entries.join("\t"))
       }
     } else {
-      let file = change.unwrap_or(state.path);
+      let file = link.unwrap_or(change.unwrap_or(state.path.clone()));
       state.cursor_y += 2;
       let mut filename = file.filename.to_string();
-      let mut prefix = format!("{}\t{}\t{} ", FILE_PREFIX, file.size, file.get_date_str());
-      if lsargs.directory && (resolved.is_empty() || file.is_dir) {
+      let prefix = format!("{}\t{}\t{} ", file.get_long_prefix(), file.size, file.get_date_str());
+      if let Some(target) = file.link {
+        filename = Colour::Cyan.bold().paint(filename).to_string();
+        if lsargs.list {
+          filename = format!("{} -> {}", filename, target);
+        }
+      } else if lsargs.directory && (resolved.is_empty() || file.is_dir) {
         filename = Colour::Blue.bold().paint(filename).to_string();
-        prefix = format!("{}\t{}\t{} ", DIR_PREFIX, file.size, file.get_date_str());
       }
       if lsargs.list {
         format!(
@@ -347,13 +1111,8 @@ pub fn ls_rec(state: &mut TermState, cmdline: &str) -> String {
       }
     };
   }
-  state.cursor_y += 2;
-  format!(
-    "{}{}: No such file or directory{}",
-    consts::NEWLINE,
-    path_str,
-    consts::NEWLINE
-  )
+  write_error!(state, format!("{}: No such file or directory", path_str));
+  "".to_string()
 }
 
 #[shell_cmd(COMMANDS, "/\t\tgo to root directory", cmd_type=CmdType::MobileOnly, name="/")]
@@ -365,26 +1124,105 @@ pub fn root(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
 #[shell_cmd(COMMANDS, "cd\t[DIR]\tchange directory")]
 pub fn cd(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
   let args: CdArgs = parse_args!(state, CdArgs::try_parse_from(cmdline.split(" ")), None);
-  let path_str = args.dir.unwrap_or("/".to_string());
-  let path = state.path.join(path_str.clone());
-  let resolved = utils::resolve_path(&path);
-  info!("{}", resolved);
-  let change = filesystem::ROOT.get_file(resolved);
-  if change.is_ok() {
-    let dir = change.unwrap();
-    if !dir.is_dir {
-      write_solo!(state, format!("can't cd to {}: Not a directory", path_str));
-      return None;
+  let path_str = args.dir.unwrap_or("~".to_string());
+  if path_str == "-" {
+    match state.oldpwd.clone() {
+      Some(dir) => {
+        change_dir(state, dir);
+        write_solo!(state, "/".to_string() + &state.path.url);
+      }
+      None => write_error!(state, "cd: OLDPWD not set"),
     }
-    state.path = dir;
-    let _ = utils::change_url(&("/".to_string() + state.path.url));
-    init!(state);
+    return None;
+  }
+  match find_dir(state, &path_str) {
+    Ok(dir) => {
+      change_dir(state, dir);
+      init!(state);
+    }
+    Err(error) => write_error!(state, error),
+  }
+  None
+}
+
+#[shell_cmd(COMMANDS, "pushd\t[DIR]\tpush directory onto stack")]
+pub fn pushd(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: PushdArgs = parse_args!(state, PushdArgs::try_parse_from(cmdline.split(" ")), None);
+  let dir = match args.dir {
+    Some(path_str) => match find_dir(state, &path_str) {
+      Ok(dir) => dir,
+      Err(error) => {
+        write_error!(state, error);
+        return None;
+      }
+    },
+    None => match state.dir_stack.pop() {
+      Some(dir) => dir,
+      None => {
+        write_error!(state, "pushd: no other directory");
+        return None;
+      }
+    },
+  };
+  state.dir_stack.push(state.path.clone());
+  change_dir(state, dir);
+  write_solo!(state, dir_stack_paths(state).join(" "));
+  None
+}
+
+#[shell_cmd(COMMANDS, "popd\t\tpop directory from stack")]
+pub fn popd(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
+  match state.dir_stack.pop() {
+    Some(dir) => {
+      change_dir(state, dir);
+      write_solo!(state, dir_stack_paths(state).join(" "));
+    }
+    None => write_error!(state, "popd: directory stack empty"),
+  }
+  None
+}
+
+#[shell_cmd(COMMANDS, "dirs\t[-v]\tprint directory stack")]
+pub fn dirs(state: &mut TermState, cmdline: &str) -> Option<Box<dyn App>> {
+  let args: DirsArgs = parse_args!(state, DirsArgs::try_parse_from(cmdline.split(" ")), None);
+  let paths = dir_stack_paths(state);
+  if args.verbose {
+    new!(state);
+    for (index, path) in paths.iter().enumerate() {
+      writeln!(state, "{:2}  {}", index, path);
+    }
+    prefix!(state);
   } else {
-    write_solo!(state, format!("{}: No such directory", path_str));
+    write_solo!(state, paths.join(" "));
   }
   None
 }
 
+fn find_dir(state: &TermState, path_str: &str) -> Result<filesystem::Node, String> {
+  let path = state.path.join(path_str);
+  let resolved = utils::resolve_path(&path);
+  info!("{}", resolved);
+  let dir = filesystem::get_file(resolved)
+    .map_err(|_| format!("{}: No such directory", path_str))?;
+  if !dir.is_dir {
+    return Err(format!("can't cd to {}: Not a directory", path_str));
+  }
+  Ok(dir)
+}
+
+fn change_dir(state: &mut TermState, dir: filesystem::Node) {
+  state.oldpwd = Some(std::mem::replace(&mut state.path, dir));
+  let _ = utils::change_url(&("/".to_string() + &state.path.url));
+}
+
+// current directory first, followed by the stack from top to bottom
+fn dir_stack_paths(state: &TermState) -> Vec<String> {
+  std::iter::once(&state.path)
+    .chain(state.dir_stack.iter().rev())
+    .map(|dir| "/".to_string() + &dir.url)
+    .collect()
+}
+
 #[shell_cmd(COMMANDS, "history\t\tprint cmd history")]
 fn history(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
   let history = CMD_HISTORY.lock().unwrap();
@@ -411,3 +1249,47 @@ pub fn help(state: &mut TermState, _args: &str) -> Option<Box<dyn App>> {
 pub fn cmds_init() {
   info!("register commands");
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::wrap;
+
+  fn normal(old: &str, new: &str) -> Vec<String> {
+    normal_diff(&TextDiff::from_lines(old, new)).iter().map(|line| wrap::strip_ansi(line)).collect()
+  }
+
+  fn unified(old: &str, new: &str) -> Vec<String> {
+    let text_diff = TextDiff::from_lines(old, new);
+    unified_diff(&text_diff, "a", "b").iter().map(|line| wrap::strip_ansi(line)).collect()
+  }
+
+  #[test]
+  fn identical_files_have_no_diff() {
+    assert!(normal("a\nb\n", "a\nb\n").is_empty());
+    assert!(unified("a\nb\n", "a\nb\n").is_empty());
+  }
+
+  #[test]
+  fn inserted_lines() {
+    assert_eq!(normal("a\nb\n", "a\nx\nb\n"), ["1a2", "> x"]);
+    assert_eq!(unified("a\nb\n", "a\nx\nb\n"), ["--- a", "+++ b", "@@ -1,2 +1,3 @@", " a", "+x", " b"]);
+  }
+
+  #[test]
+  fn deleted_lines() {
+    assert_eq!(normal("a\nb\nc\n", "a\nc\n"), ["2d1", "< b"]);
+    assert_eq!(normal("a\nb\nc\n", "a\n"), ["2,3d1", "< b", "< c"]);
+    assert_eq!(unified("a\nb\nc\n", "a\nc\n"), ["--- a", "+++ b", "@@ -1,3 +1,2 @@", " a", "-b", " c"]);
+  }
+
+  #[test]
+  fn mixed_hunks() {
+    let (old, new) = ("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+    assert_eq!(normal(old, new), ["2c2", "< b", "---", "> B", "4a5", "> e"]);
+    assert_eq!(
+      unified(old, new),
+      ["--- a", "+++ b", "@@ -1,4 +1,5 @@", " a", "-b", "+B", " c", " d", "+e"]
+    );
+  }
+}
@@ -9,8 +9,11 @@ pub const PAGE_START: &str = "\x1b\x5b\x48";
 pub const PAGE_END: &str = "\x1b\x5b\x46";
 pub const INSERT: &str = "\x1b\x5b\x32\x7e";
 pub const RETURN: &str = "\x1b\x5b\x44 \x1b\x5b\x44";
+pub const ERASE_LINE: &str = "\x1b\x5b\x32\x4b";
 pub const NEWLINE: &str = "\n\r";
 pub const PREFIX: &str = "$ ";
+// Directory `~` resolves to, build.rs checks that it exists, SITE_HOME overrides it
+pub const HOME: &str = env!("SITE_HOME");
 // Function Keys
 pub const F1: &str = "\x1b\x4f\x50";
 pub const F2: &str = "\x1b\x4f\x51";
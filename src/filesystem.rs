@@ -1,61 +1,321 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::Arc;
 
-use lazy_static::lazy_static;
 use log::info;
-use serde::Deserialize;
 
-use crate::utils;
+use crate::cache;
+use crate::manifest;
+use crate::overlay;
+use crate::providers::{self, Provider};
+use crate::{text, utils};
 
+// generated static tree, ROOT, UNION_MOUNTS and whether directories are listed by MANIFESTS
 include!(concat!(env!("OUT_DIR"), "/root.rs"));
 
-lazy_static! {
-    pub static ref ROOT: Entry = Entry::new();
-}
-
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 pub struct Entry {
-  pub filename: &'static str,
-  pub url: &'static str,
+  pub filename: Cow<'static, str>,
+  pub url: Cow<'static, str>,
   pub size: u64,
+  pub mode: u32,
+  pub nlink: u64,
+  pub owner: &'static str,
+  pub group: &'static str,
   pub modified: u64,
+  // first commit of the file, its modification time without history
+  pub created: u64,
   pub is_dir: bool,
-  pub entries: HashMap<&'static str, Entry>, // only applicable to Dirs
+  pub link: Option<&'static str>, // only applicable to Links
+  pub sha256: Option<&'static str>, // only applicable to Files of the static tree
+  pub meta: Option<Meta>, // only applicable to markdown Files with front matter
+  pub provider: Option<Provider>, // only applicable to generated Files
+  pub content: Option<Content>, // only applicable to overlay and inlined Files
+  pub entries: &'static [Entry], // only applicable to Dirs, sorted by filename
+}
+
+// front matter of a markdown file
+#[derive(Debug)]
+pub struct Meta {
+  pub title: Option<&'static str>,
+  pub date: Option<u64>, // declared publication date
+  pub tags: &'static [&'static str],
+  pub summary: Option<&'static str>,
+  pub draft: bool,
+}
+
+#[derive(Debug)]
+pub enum Content {
+  Text(String),
+  Inline(&'static str), // small file compiled into the binary
+  Lower(&'static Entry), // unmodified copy of a file of the static tree
+}
+
+// entry of the merged view, either from the static tree or the writable overlay
+#[derive(Clone, Debug)]
+pub enum Node {
+  Lower(&'static Entry),
+  Upper(Arc<Entry>),
+}
+
+impl Deref for Node {
+  type Target = Entry;
+
+  fn deref(&self) -> &Entry {
+    match self {
+      Node::Lower(entry) => entry,
+      Node::Upper(entry) => entry,
+    }
+  }
 }
 
-const ROOT_URL: &str = "/root/";
+pub const ROOT_URL: &str = "/root/";
+const MAX_LINKS: usize = 40;
+
+pub fn root() -> Node {
+  Node::Lower(&ROOT)
+}
+
+fn lookup(path: &str, follow: bool) -> Result<Node, String> {
+  walk(path, follow, &mut |_dir| {})
+}
+
+// lookup that shows `visit` every directory it looks into
+fn walk(path: &str, follow: bool, visit: &mut dyn FnMut(&Entry)) -> Result<Node, String> {
+  walk_from(root(), path, follow, visit)
+}
+
+// walk through any tree, the tests use one of their own
+fn walk_from(root: Node, path: &str, follow: bool, visit: &mut dyn FnMut(&Entry)) -> Result<Node, String> {
+  let mut path = path.to_string();
+  for _ in 0..MAX_LINKS {
+    let files = utils::resolve_path_files(&path);
+    let mut node = root.clone();
+    let mut link = None;
+    for (index, name) in files.iter().enumerate() {
+      visit(&node);
+      let entry = node.child(name).ok_or_else(|| "File not found".to_string())?;
+      let remaining_files = &files[index + 1..];
+      // links inside the path are always followed, the last one only if requested
+      if let Some(target) = entry.link {
+        if follow || !remaining_files.is_empty() {
+          link = Some(entry.link_path(target) + "/" + &remaining_files.join("/"));
+          break;
+        }
+      }
+      node = entry;
+    }
+    match link {
+      // link targets are absolute, so continue from the root
+      Some(target) => path = target,
+      None => {
+        visit(&node);
+        return Ok(node);
+      }
+    }
+  }
+  Err("Too many levels of symbolic links".to_string())
+}
+
+pub fn get_file(path_str: impl Into<String>) -> Result<Node, String> {
+  lookup(&path_str.into(), true)
+}
+
+pub fn get_file_nofollow(path_str: impl Into<String>) -> Result<Node, String> {
+  lookup(&path_str.into(), false)
+}
+
+// loads the manifests of all directories the paths lead through, the sync lookups only see loaded ones
+pub async fn prefetch(paths: Vec<String>) {
+  if !MANIFESTS {
+    return;
+  }
+  for path in paths {
+    // a failed load leaves the directory empty until the next command loads it again
+    while let Some(url) = missing_manifest(&path) {
+      if manifest::load(&url).await.is_err() {
+        break;
+      }
+    }
+  }
+}
+
+// prefetch of everything below a directory, for listings that recurse
+pub async fn prefetch_tree(path: String) {
+  if !MANIFESTS {
+    return;
+  }
+  let mut dirs = vec![path];
+  while let Some(dir) = dirs.pop() {
+    prefetch(vec![dir.clone()]).await;
+    if let Ok(node) = get_file(dir) {
+      for (_name, child) in node.children() {
+        if child.is_dir {
+          dirs.push(child.url.to_string());
+        }
+      }
+    }
+  }
+}
+
+// completion can't wait, it sees the directory the next time
+pub fn prefetch_later(path: String) {
+  if MANIFESTS && missing_manifest(&path).is_some() {
+    wasm_bindgen_futures::spawn_local(prefetch(vec![path]));
+  }
+}
+
+fn missing_manifest(path: &str) -> Option<String> {
+  let mut missing = None;
+  let _ = walk(path, true, &mut |dir| {
+    if missing.is_none() {
+      missing = dir.missing_manifest();
+    }
+  });
+  missing
+}
+
+// file of the static tree or the provided files, ignoring unions and the overlay
+pub fn get_lower(path: &str) -> Option<&'static Entry> {
+  let files = utils::resolve_path_files(path);
+  [&ROOT, providers::root()]
+    .into_iter()
+    .find_map(|root| files.iter().try_fold(root, |dir, name| dir.entry(name)))
+}
+
+impl Node {
+  pub fn child(&self, name: &str) -> Option<Node> {
+    let path = self.child_path(name);
+    if let Some(upper) = overlay::get(&path) {
+      return overlay::node(&path, &upper);
+    }
+    match self {
+      Node::Lower(entry) => {
+        let child = entry.child(name)?;
+        // entries unioned in from elsewhere are changed at their real location
+        match overlay::get(&child.url) {
+          Some(upper) => overlay::node(&child.url, &upper),
+          None => Some(Node::Lower(child)),
+        }
+      }
+      // overlay directories hide everything below them
+      Node::Upper(_) => None,
+    }
+  }
+
+  // entries of a directory in the merged view
+  pub fn children(&self) -> Vec<(String, Node)> {
+    let mut children: Vec<(String, Node)> = match self {
+      Node::Lower(entry) => entry
+        .children()
+        .into_iter()
+        .filter(|(name, _child)| overlay::get(&self.child_path(name)).is_none())
+        .filter_map(|(name, child)| match overlay::get(&child.url) {
+          Some(upper) => Some((name.to_string(), overlay::node(&child.url, &upper)?)),
+          None => Some((name.to_string(), Node::Lower(child))),
+        })
+        .collect(),
+      Node::Upper(_) => vec![],
+    };
+    for (path, upper) in overlay::list(&self.url) {
+      if let Some(node) = overlay::node(&path, &upper) {
+        children.push((node.filename.to_string(), node));
+      }
+    }
+    children
+  }
+}
 
 impl Entry {
-  pub fn new() -> Entry {
-    ron::from_str(ROOT_SERIALIZED).unwrap()
-  }
-
-  fn get_file_rec(&self, files: &[&str]) -> Result<&Entry, String> {
-      let entry = match self.entries.get(files[0]) {
-          Some(e) => e,
-          None => {
-              self.entries.get("old")
-                  .ok_or_else(|| "File not found".to_string())?
-                  .entries.get(files[0])
-                  .ok_or_else(|| "File not found".to_string())?
-          }
-      };
+  // entries of a directory, chunked builds list the ones of the static tree in manifests
+  fn entries(&self) -> &'static [Entry] {
+    if self.is_listed() {
+      return manifest::entries(&self.url).unwrap_or(&[]);
+    }
+    self.entries
+  }
+
+  fn is_listed(&self) -> bool {
+    MANIFESTS && self.is_dir && self.provider.is_none() && self.entries.is_empty()
+  }
 
-      let remaining_files = &files[1..];
+  // manifest of this directory or of one unioned into it that isn't loaded yet
+  fn missing_manifest(&self) -> Option<String> {
+    if !self.is_listed() {
+      return None;
+    }
+    let sources = UNION_MOUNTS
+      .iter()
+      .filter(|(target, _source)| target.trim_matches('/') == self.url)
+      .map(|(_target, source)| source.trim_matches('/'));
+    [self.url.as_ref()]
+      .into_iter()
+      .chain(sources)
+      .find(|url| manifest::entries(url).is_none())
+      .map(|url| url.to_string())
+  }
 
-      if remaining_files.is_empty() {
-          Ok(entry)
-      } else {
-          entry.get_file_rec(remaining_files)
+  // direct entry of a directory, found by binary search
+  fn entry(&self, name: &str) -> Option<&'static Entry> {
+    let entries = self.entries();
+    entries
+      .binary_search_by(|entry| entry.filename.as_ref().cmp(name))
+      .ok()
+      .map(|index| &entries[index])
+  }
+
+  // directories unioned into this one by UNION_MOUNTS, the provided files are mounted over the root
+  fn unions(&self) -> impl Iterator<Item = &'static Entry> + '_ {
+    let provided = std::ptr::eq(self, &ROOT).then(providers::root);
+    UNION_MOUNTS
+      .iter()
+      .filter(|(target, _source)| target.trim_matches('/') == self.url)
+      // sources are plain paths, resolving them through unions could recurse forever
+      .filter_map(|(_target, source)| get_lower(source))
+      .filter(|source| source.is_dir)
+      .chain(provided)
+  }
+
+  fn child(&self, name: &str) -> Option<&Entry> {
+    match self.entry(name) {
+      Some(entry) => Some(entry),
+      None => self.unions().find_map(|source| source.child(name)),
+    }
+  }
+
+  // entries of a directory including the ones unioned into it
+  fn children(&self) -> Vec<(&'static str, &Entry)> {
+    let mut children: Vec<(&'static str, &Entry)> =
+      self.entries().iter().map(|entry| (entry.filename.as_ref(), entry)).collect();
+    for source in self.unions() {
+      for (name, entry) in source.children() {
+        if !children.iter().any(|(child, _)| *child == name) {
+          children.push((name, entry));
+        }
       }
+    }
+    children
   }
 
-  pub fn get_file(&self, path_str: impl Into<String>) -> Result<&Entry, String> {
-    let path = path_str.into();
-    let files = utils::resolve_path_files(&path);
-    if files.is_empty() {
-      return Ok(self);
+  // url of a direct child, the root has an empty url
+  pub fn child_path(&self, name: &str) -> String {
+    if self.url.is_empty() {
+      name.to_string()
+    } else {
+      format!("{}/{}", self.url, name)
+    }
+  }
+
+  // absolute path of a link target, relative targets start at the link's directory
+  fn link_path(&self, target: &str) -> String {
+    if target.starts_with("/") {
+      return target.to_string();
     }
-    self.get_file_rec(&files)
+    let parent = match self.url.rfind("/") {
+      Some(off) => &self.url[..off],
+      None => "",
+    };
+    format!("/{}/{}", parent, target)
   }
 
   pub fn get_size(&self, human: bool) -> String {
@@ -66,6 +326,34 @@ impl Entry {
     }
   }
 
+  pub fn get_mode_str(&self) -> String {
+    let mut mode = if self.link.is_some() {
+      "l"
+    } else if self.is_dir {
+      "d"
+    } else {
+      "-"
+    }
+    .to_string();
+    for shift in [6, 3, 0] {
+      let bits = self.mode >> shift;
+      mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+      mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+      mode.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    mode
+  }
+
+  // mode, link count, owner and group columns of a long listing
+  pub fn get_long_prefix(&self) -> String {
+    format!("{}\t{} {}\t{}", self.get_mode_str(), self.nlink, self.owner, self.group)
+  }
+
+  // visitors only get the permissions of others
+  pub fn is_readable(&self) -> bool {
+    self.mode & 0o4 != 0
+  }
+
   pub fn get_date_str(&self) -> String {
     let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(
       self.modified as i64, 0,
@@ -76,16 +364,215 @@ impl Entry {
 
   pub fn join(&self, path_str: impl Into<String>) -> String {
     let path = path_str.into();
-    if path.starts_with("/") {
+    // resolve_path expands ~ to the home directory
+    if path.starts_with("/") || utils::expand_home(&path).is_some() {
       path
     } else {
       self.url.to_string() + "/" + &path
     }
   }
 
-  pub fn load(&self) -> Result<String, String> {
-    let url = ROOT_URL.to_string() + self.url;
+  // content that is available without a request, None if it has to be fetched
+  pub fn local_content(&self) -> Option<Result<String, String>> {
+    if !self.is_readable() {
+      return Some(Err(format!("{}: Permission denied", self.filename)));
+    }
+    if let Some(provider) = self.provider {
+      return Some(Ok(provider()));
+    }
+    match &self.content {
+      Some(Content::Text(content)) => Some(Ok(content.clone())),
+      Some(Content::Inline(content)) => Some(Ok(self.shown(content))),
+      Some(Content::Lower(entry)) => entry.local_content(),
+      None => None,
+    }
+  }
+
+  pub async fn load(&self) -> Result<String, String> {
+    if let Some(content) = self.local_content() {
+      return content;
+    }
+    let entry = match &self.content {
+      Some(Content::Lower(entry)) => *entry,
+      _ => self,
+    };
+    let url = ROOT_URL.to_string() + &entry.url;
     info!("loading url: {}", url);
-    utils::fetch(url)
+    let content = cache::load(&url, entry.modified, entry.sha256).await?;
+    Ok(entry.shown(&content))
+  }
+
+  // front matter is metadata, only the body is shown
+  fn shown(&self, content: &str) -> String {
+    if self.meta.is_some() {
+      text::body(content).to_string()
+    } else {
+      content.to_string()
+    }
+  }
+
+  pub fn has_tag(&self, tag: &str) -> bool {
+    self.meta.as_ref().is_some_and(|meta| meta.tags.contains(&tag))
+  }
+
+  // declared date of a post, the modification time otherwise
+  pub fn date(&self) -> u64 {
+    self.meta.as_ref().and_then(|meta| meta.date).unwrap_or(self.modified)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(url: &'static str, is_dir: bool, link: Option<&'static str>, mut entries: Vec<Entry>) -> Entry {
+    entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Entry {
+      filename: Cow::Borrowed(url.rsplit('/').next().unwrap_or(url)),
+      url: Cow::Borrowed(url),
+      size: 0,
+      mode: if is_dir { 0o755 } else { 0o644 },
+      nlink: 1,
+      owner: "root",
+      group: "root",
+      modified: 0,
+      created: 0,
+      is_dir,
+      link,
+      sha256: None,
+      meta: None,
+      provider: None,
+      content: None,
+      entries: Box::leak(entries.into_boxed_slice()),
+    }
+  }
+
+  fn file(url: &'static str) -> Entry {
+    entry(url, false, None, vec![])
+  }
+
+  fn link(url: &'static str, target: &'static str) -> Entry {
+    entry(url, false, Some(target), vec![])
+  }
+
+  // tree of its own under /fixture, so the tests depend neither on the site content nor on the overlay
+  fn tree() -> Node {
+    let docs = entry(
+      "fixture/docs",
+      true,
+      None,
+      vec![
+        file("fixture/docs/guide.md"),
+        link("fixture/docs/up", "../readme.md"),
+        link("fixture/docs/here", "guide.md"),
+        link("fixture/docs/absolute", "/fixture/docs/guide.md"),
+      ],
+    );
+    let fixture = entry(
+      "fixture",
+      true,
+      None,
+      vec![
+        docs,
+        file("fixture/readme.md"),
+        link("fixture/docs-link", "docs"),
+        link("fixture/loop1", "loop2"),
+        link("fixture/loop2", "loop1"),
+        link("fixture/dangling", "missing.md"),
+      ],
+    );
+    Node::Lower(Box::leak(Box::new(entry("", true, None, vec![fixture]))))
+  }
+
+  fn url(path: &str, follow: bool) -> Result<String, String> {
+    walk_from(tree(), &format!("fixture/{}", path), follow, &mut |_dir| {}).map(|node| node.url.to_string())
+  }
+
+  #[test]
+  fn links_resolve_relative_to_their_directory() {
+    assert_eq!(url("docs/up", true).as_deref(), Ok("fixture/readme.md"));
+    assert_eq!(url("docs/here", true).as_deref(), Ok("fixture/docs/guide.md"));
+    assert_eq!(url("docs/absolute", true).as_deref(), Ok("fixture/docs/guide.md"));
+  }
+
+  #[test]
+  fn links_inside_a_path_are_always_followed() {
+    assert_eq!(url("docs-link/guide.md", false).as_deref(), Ok("fixture/docs/guide.md"));
+    assert_eq!(url("docs-link", false).as_deref(), Ok("fixture/docs-link"));
+    assert_eq!(url("docs-link", true).as_deref(), Ok("fixture/docs"));
+  }
+
+  #[test]
+  fn link_cycles_stop() {
+    let too_many = Err("Too many levels of symbolic links".to_string());
+    assert_eq!(url("loop1", true), too_many);
+    assert_eq!(url("loop1/file", false), too_many);
+    assert_eq!(url("loop1", false).as_deref(), Ok("fixture/loop1"));
+  }
+
+  #[test]
+  fn dangling_links() {
+    assert_eq!(url("dangling", true), Err("File not found".to_string()));
+    let link = walk_from(tree(), "fixture/dangling", false, &mut |_dir| {}).unwrap();
+    assert_eq!(link.link, Some("missing.md"));
+  }
+
+  // unions of the static tree with the build's UNION_MOUNTS, whatever the site content is
+  #[test]
+  fn own_entries_shadow_unioned_ones() {
+    for (target, _source) in UNION_MOUNTS {
+      let Some(dir) = get_lower(target) else {
+        continue;
+      };
+      for source in dir.unions() {
+        for child in source.entries() {
+          let name = child.filename.as_ref();
+          // the directory's own entry, otherwise the one of the first mount that has it
+          let expected = dir.entry(name).or_else(|| dir.unions().find_map(|source| source.entry(name)));
+          assert_eq!(dir.child(name).map(|entry| &entry.url), expected.map(|entry| &entry.url));
+        }
+      }
+      // the same directory with every unioned name of its own
+      let leak = |text: String| -> &'static str { Box::leak(text.into_boxed_str()) };
+      let mut names: Vec<&str> = dir
+        .unions()
+        .flat_map(|source| source.entries())
+        .map(|child| child.filename.as_ref())
+        .collect();
+      names.sort();
+      names.dedup();
+      let shadowing = entry(
+        leak(dir.url.to_string()),
+        true,
+        None,
+        names.iter().map(|name| file(leak(dir.child_path(name)))).collect(),
+      );
+      for name in names {
+        assert!(std::ptr::eq(shadowing.child(name).unwrap(), shadowing.entry(name).unwrap()));
+      }
+    }
+  }
+
+  #[test]
+  fn provided_files_are_mounted_over_the_root() {
+    if ROOT.entry("proc").is_some() {
+      return;
+    }
+    let proc = ROOT.child("proc").unwrap();
+    assert!(proc.is_dir);
+    assert!(proc.child("version").is_some_and(|version| version.provider.is_some()));
+    assert!(get_lower("proc/version").is_some());
+  }
+
+  #[test]
+  fn children_list_every_name_once() {
+    let children = ROOT.children();
+    let mut names: Vec<&str> = children.iter().map(|(name, _entry)| *name).collect();
+    // the root's own entries come first
+    let own: Vec<&str> = ROOT.entries().iter().map(|entry| entry.filename.as_ref()).collect();
+    assert_eq!(names[..own.len()], own);
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), children.len());
   }
 }
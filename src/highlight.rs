@@ -0,0 +1,461 @@
+use ansi_term::{Colour, Style};
+
+// a language the highlighter knows, found by file extension or the interpreter of a shebang
+struct Language {
+  extensions: &'static [&'static str],
+  interpreters: &'static [&'static str],
+  keywords: &'static [&'static str],
+  // types and builtin functions
+  builtins: &'static [&'static str],
+  line_comments: &'static [&'static str],
+  block_comment: Option<(&'static str, &'static str)>,
+  // string delimiters that can span lines, e.g. python docstrings
+  multiline_strings: &'static [&'static str],
+  quotes: &'static [char],
+  // ' starts a char literal only if it closes right away, otherwise it is a lifetime
+  char_literals: bool,
+  // $name and ${name}
+  variables: bool,
+  // `name!` macros, @decorators and #directives
+  macros: bool,
+  decorators: bool,
+  directives: bool,
+}
+
+const LANGUAGES: &[Language] = &[
+  // rust
+  Language {
+    extensions: &["rs"],
+    interpreters: &[],
+    keywords: &[
+      "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+      "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self",
+      "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    builtins: &[
+      "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8", "u16", "u32", "u64",
+      "u128", "usize",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    multiline_strings: &[],
+    quotes: &['"', '\''],
+    char_literals: true,
+    variables: false,
+    macros: true,
+    decorators: false,
+    directives: false,
+  },
+  // python
+  Language {
+    extensions: &["py", "pyw", "sage"],
+    interpreters: &["python", "python2", "python3", "sage"],
+    keywords: &[
+      "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+      "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+      "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+    ],
+    builtins: &[
+      "bytes", "bytearray", "dict", "float", "int", "list", "object", "set", "str", "tuple", "print", "len", "range",
+      "self",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    multiline_strings: &["\"\"\"", "'''"],
+    quotes: &['"', '\''],
+    char_literals: false,
+    variables: false,
+    macros: false,
+    decorators: true,
+    directives: false,
+  },
+  // c and c++
+  Language {
+    extensions: &["c", "h", "cc", "cpp", "cxx", "hh", "hpp"],
+    interpreters: &[],
+    keywords: &[
+      "auto", "break", "case", "catch", "class", "const", "constexpr", "continue", "default", "delete", "do", "else",
+      "enum", "extern", "false", "for", "goto", "if", "inline", "namespace", "new", "nullptr", "private",
+      "protected", "public", "register", "return", "sizeof", "static", "struct", "switch", "template", "this",
+      "throw", "true", "try", "typedef", "union", "using", "virtual", "volatile", "while", "NULL",
+    ],
+    builtins: &[
+      "bool", "char", "double", "float", "int", "long", "short", "signed", "unsigned", "void", "size_t", "ssize_t",
+      "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "uintptr_t", "FILE",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    multiline_strings: &[],
+    quotes: &['"', '\''],
+    char_literals: false,
+    variables: false,
+    macros: false,
+    decorators: false,
+    directives: true,
+  },
+  // shell
+  Language {
+    extensions: &["sh", "bash", "zsh"],
+    interpreters: &["sh", "bash", "zsh", "dash"],
+    keywords: &[
+      "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local", "return",
+      "select", "then", "until", "while",
+    ],
+    builtins: &[
+      "cd", "echo", "eval", "exec", "exit", "printf", "read", "set", "shift", "source", "test", "trap", "unset",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    multiline_strings: &[],
+    quotes: &['"', '\'', '`'],
+    char_literals: false,
+    variables: true,
+    macros: false,
+    decorators: false,
+    directives: false,
+  },
+  // javascript and typescript
+  Language {
+    extensions: &["js", "mjs", "cjs", "ts", "jsx", "tsx"],
+    interpreters: &["node", "deno", "bun"],
+    keywords: &[
+      "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do", "else",
+      "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in", "instanceof",
+      "let", "new", "null", "of", "return", "static", "super", "switch", "this", "throw", "true", "try", "typeof",
+      "undefined", "var", "void", "while", "yield",
+    ],
+    builtins: &["any", "boolean", "number", "string", "unknown", "never", "interface", "type", "enum"],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    multiline_strings: &["`"],
+    quotes: &['"', '\''],
+    char_literals: false,
+    variables: false,
+    macros: false,
+    decorators: true,
+    directives: false,
+  },
+  // go
+  Language {
+    extensions: &["go"],
+    interpreters: &[],
+    keywords: &[
+      "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "false", "for",
+      "func", "go", "goto", "if", "import", "interface", "map", "nil", "package", "range", "return", "select",
+      "struct", "switch", "true", "type", "var",
+    ],
+    builtins: &[
+      "bool", "byte", "error", "float32", "float64", "int", "int8", "int16", "int32", "int64", "rune", "string",
+      "uint", "uint8", "uint16", "uint32", "uint64", "uintptr",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    multiline_strings: &["`"],
+    quotes: &['"', '\''],
+    char_literals: false,
+    variables: false,
+    macros: false,
+    decorators: false,
+    directives: false,
+  },
+];
+
+fn keyword() -> Style {
+  Colour::Purple.bold()
+}
+
+fn typename() -> Style {
+  Colour::Yellow.normal()
+}
+
+fn string() -> Style {
+  Colour::Green.normal()
+}
+
+fn number() -> Style {
+  Colour::Cyan.normal()
+}
+
+fn comment() -> Style {
+  Colour::Fixed(244).italic()
+}
+
+fn function() -> Style {
+  Colour::Blue.bold()
+}
+
+fn special() -> Style {
+  Colour::Red.normal()
+}
+
+// what is still open at the end of a line
+enum Open {
+  Comment,
+  String(&'static str),
+}
+
+fn language(filename: &str, content: &str) -> Option<&'static Language> {
+  if let Some((_name, extension)) = filename.rsplit_once('.') {
+    let extension = extension.to_lowercase();
+    if let Some(language) = LANGUAGES.iter().find(|language| language.extensions.contains(&extension.as_str())) {
+      return Some(language);
+    }
+  }
+  // #!/usr/bin/python3, #!/usr/bin/env -S python3 -u
+  let shebang = content.lines().next()?.strip_prefix("#!")?;
+  let mut words = shebang.split_whitespace().filter(|word| !word.starts_with('-'));
+  let mut interpreter = words.next()?.rsplit('/').next()?;
+  if interpreter == "env" {
+    interpreter = words.next()?;
+  }
+  LANGUAGES
+    .iter()
+    .find(|language| language.interpreters.contains(&interpreter))
+}
+
+// source files with ANSI colors, None if the language is unknown or the file already has escape sequences
+pub fn highlight(filename: &str, content: &str) -> Option<String> {
+  if content.contains('\x1b') {
+    return None;
+  }
+  let language = language(filename, content)?;
+  let mut open = None;
+  let lines: Vec<String> = content
+    .lines()
+    .map(|line| highlight_line(language, line, &mut open))
+    .collect();
+  Some(lines.join("\n"))
+}
+
+fn highlight_line(language: &Language, line: &str, open: &mut Option<Open>) -> String {
+  let mut out = String::new();
+  let mut rest = line;
+  let mut previous: Option<char> = None;
+  while !rest.is_empty() {
+    // comments and strings that started on an earlier line
+    if let Some(started) = open.take() {
+      let (style, end) = match started {
+        Open::Comment => (comment(), language.block_comment.map_or("", |(_start, end)| end)),
+        Open::String(delimiter) => (string(), delimiter),
+      };
+      match rest.find(end).filter(|_| !end.is_empty()) {
+        Some(offset) => {
+          let (token, tail) = rest.split_at(offset + end.len());
+          out.push_str(&style.paint(token).to_string());
+          rest = tail;
+        }
+        None => {
+          out.push_str(&style.paint(rest).to_string());
+          *open = Some(started);
+          return out;
+        }
+      }
+      continue;
+    }
+    let c = rest.chars().next().unwrap();
+    let after_space = previous.is_none_or(char::is_whitespace);
+    let line_comment = language.line_comments.iter().any(|marker| {
+      // # also appears in $# and ${#name} of shell scripts
+      rest.starts_with(marker) && (*marker != "#" || !language.variables || after_space)
+    });
+    if line_comment {
+      out.push_str(&comment().paint(rest).to_string());
+      return out;
+    }
+    if let Some((start, end)) = language.block_comment.filter(|(start, _end)| rest.starts_with(start)) {
+      match rest[start.len()..].find(end) {
+        Some(offset) => {
+          let (token, tail) = rest.split_at(start.len() + offset + end.len());
+          out.push_str(&comment().paint(token).to_string());
+          rest = tail;
+        }
+        None => {
+          out.push_str(&comment().paint(rest).to_string());
+          *open = Some(Open::Comment);
+          return out;
+        }
+      }
+      previous = Some(' ');
+      continue;
+    }
+    if let Some(delimiter) = language.multiline_strings.iter().find(|delimiter| rest.starts_with(**delimiter)) {
+      match rest[delimiter.len()..].find(delimiter) {
+        Some(offset) => {
+          let (token, tail) = rest.split_at(delimiter.len() + offset + delimiter.len());
+          out.push_str(&string().paint(token).to_string());
+          rest = tail;
+        }
+        None => {
+          out.push_str(&string().paint(rest).to_string());
+          *open = Some(Open::String(delimiter));
+          return out;
+        }
+      }
+      previous = Some(' ');
+      continue;
+    }
+    let (token, style) = if language.quotes.contains(&c) && !(c == '\'' && language.char_literals && !is_char_literal(rest)) {
+      // shell strings in single quotes have no escapes
+      (quoted(rest, c, !(language.variables && c == '\'')), Some(string()))
+    } else if language.directives && c == '#' && line[..line.len() - rest.len()].trim().is_empty() {
+      (word(&rest[1..]).map_or("#", |name| &rest[..name.len() + 1]), Some(special()))
+    } else if language.decorators && c == '@' {
+      (word(&rest[1..]).map_or("@", |name| &rest[..name.len() + 1]), Some(special()))
+    } else if language.variables && c == '$' {
+      (variable(rest), Some(special()))
+    } else if c.is_ascii_digit() && !previous.is_some_and(|previous| previous.is_alphanumeric() || previous == '_') {
+      let length = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(rest.len());
+      (&rest[..length], Some(number()))
+    } else if let Some(name) = word(rest) {
+      let next = rest[name.len()..].trim_start().chars().next();
+      let style = if language.keywords.contains(&name) {
+        Some(keyword())
+      } else if language.macros && rest[name.len()..].starts_with('!') {
+        Some(special())
+      } else if language.builtins.contains(&name) {
+        Some(typename())
+      } else if next == Some('(') {
+        Some(function())
+      } else if !language.variables && name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        Some(typename())
+      } else {
+        None
+      };
+      (name, style)
+    } else {
+      (&rest[..c.len_utf8()], None)
+    };
+    match style {
+      Some(style) => out.push_str(&style.paint(token).to_string()),
+      None => out.push_str(token),
+    }
+    previous = token.chars().last();
+    rest = &rest[token.len()..];
+  }
+  out
+}
+
+// an identifier at the start of `text`
+fn word(text: &str) -> Option<&str> {
+  if !text.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+    return None;
+  }
+  let length = text
+    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+    .unwrap_or(text.len());
+  Some(&text[..length])
+}
+
+// a string up to its closing quote, the rest of the line if it isn't closed
+fn quoted(text: &str, quote: char, escapes: bool) -> &str {
+  let mut escaped = false;
+  for (offset, c) in text.char_indices().skip(1) {
+    if escaped {
+      escaped = false;
+    } else if c == '\\' && escapes {
+      escaped = true;
+    } else if c == quote {
+      return &text[..offset + c.len_utf8()];
+    }
+  }
+  text
+}
+
+// 'a' and '\n', but not the lifetime in &'a str
+fn is_char_literal(text: &str) -> bool {
+  let mut chars = text.chars().skip(1);
+  matches!((chars.next(), chars.next()), (Some('\\'), _) | (Some(_), Some('\'')))
+}
+
+// $name, $1, $@ and ${name}
+fn variable(text: &str) -> &str {
+  let rest = &text[1..];
+  if rest.starts_with('{') {
+    return rest.find('}').map_or(text, |end| &text[..end + 2]);
+  }
+  if let Some(name) = word(rest) {
+    return &text[..name.len() + 1];
+  }
+  match rest.chars().next() {
+    Some(c) if c.is_ascii_digit() || "@#?$!*-".contains(c) => &text[..2],
+    _ => "$",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn painted(style: Style, text: &str) -> String {
+    style.paint(text).to_string()
+  }
+
+  #[test]
+  fn rust_tokens() {
+    let highlighted = highlight("main.rs", "fn main() -> u8 { 0x41 }").unwrap();
+    assert!(highlighted.contains(&painted(keyword(), "fn")));
+    assert!(highlighted.contains(&painted(function(), "main")));
+    assert!(highlighted.contains(&painted(typename(), "u8")));
+    assert!(highlighted.contains(&painted(number(), "0x41")));
+  }
+
+  #[test]
+  fn comments_inside_strings_stay_strings() {
+    let highlighted = highlight("a.rs", "let s = \"a // \\\"b\"; // c").unwrap();
+    assert!(highlighted.contains(&painted(string(), "\"a // \\\"b\"")));
+    assert!(highlighted.ends_with(&painted(comment(), "// c")));
+  }
+
+  #[test]
+  fn lifetimes_are_no_char_literals() {
+    let highlighted = highlight("a.rs", "fn f<'a>(s: &'a str) -> char { 'x' }").unwrap();
+    assert!(highlighted.contains(&painted(string(), "'x'")));
+    assert!(!highlighted.contains(&painted(string(), "'a>(s: &'")));
+  }
+
+  #[test]
+  fn block_comments_and_docstrings_span_lines() {
+    let highlighted = highlight("a.c", "/* one\ntwo */ int x;").unwrap();
+    let lines: Vec<&str> = highlighted.lines().collect();
+    assert_eq!(lines[0], painted(comment(), "/* one"));
+    assert!(lines[1].starts_with(&painted(comment(), "two */")));
+    let highlighted = highlight("a.py", "\"\"\"doc\nstring\"\"\"\npass").unwrap();
+    assert_eq!(highlighted.lines().nth(1), Some(painted(string(), "string\"\"\"").as_str()));
+    assert_eq!(highlighted.lines().nth(2), Some(painted(keyword(), "pass").as_str()));
+  }
+
+  #[test]
+  fn shebangs_pick_the_language() {
+    let highlighted = highlight("exploit", "#!/usr/bin/env python3\nimport os").unwrap();
+    assert!(highlighted.contains(&painted(keyword(), "import")));
+    assert!(highlight("run", "#!/bin/bash\necho hi").is_some());
+    assert!(highlight("notes", "just text").is_none());
+  }
+
+  #[test]
+  fn shell_variables_are_no_comments() {
+    let highlighted = highlight("a.sh", "echo $# ${#list} # count").unwrap();
+    assert!(highlighted.contains(&painted(special(), "$#")));
+    assert!(highlighted.contains(&painted(special(), "${#list}")));
+    assert!(highlighted.ends_with(&painted(comment(), "# count")));
+    assert!(!highlight("a.sh", "x=1#y").unwrap().contains(&painted(comment(), "#y")));
+    // single quotes don't escape
+    assert!(highlight("a.sh", "echo 'a\\' b").unwrap().contains(&painted(string(), "'a\\'")));
+  }
+
+  #[test]
+  fn unknown_or_colored_content_is_left_alone() {
+    assert!(highlight("notes.txt", "fn main() {}").is_none());
+    assert!(highlight("a.rs", "\x1b[31mfn\x1b[0m main() {}").is_none());
+  }
+
+  #[test]
+  fn lines_are_kept() {
+    let content = "fn a() {}\n\n// b\nfn c() {}";
+    let highlighted = highlight("a.rs", content).unwrap();
+    let plain: Vec<String> = highlighted.lines().map(crate::wrap::strip_ansi).collect();
+    assert_eq!(plain, content.lines().collect::<Vec<_>>());
+  }
+}
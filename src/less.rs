@@ -4,11 +4,20 @@ use log::info;
 use crate::app::App;
 use crate::shell::Shell;
 use crate::termstate::TermState;
-use crate::{consts, filesystem, utils, write};
+use crate::{consts, filesystem, highlight, pending, utils, wrap, write};
+
+// columns markdown posts are rendered with
+const RENDERED_WIDTH: usize = 60;
 
 pub struct Less {
   ansi_buffer: Vec<char>,
-  lines: Vec<&'static str>,
+  // lines of the file
+  source: Vec<&'static str>,
+  // lines as shown, wrapped to the terminal width, and the source line each one belongs to
+  lines: Vec<String>,
+  sources: Vec<usize>,
+  // rendered markdown, its paragraphs are joined and wrapped again on narrow screens
+  markdown: bool,
   line: usize,
   ansi: bool,
 }
@@ -30,7 +39,7 @@ impl App for Less {
       }
       // quit
       'q' => {
-        let _ = utils::change_url(&("/".to_string() + state.path.url));
+        let _ = utils::change_url(&("/".to_string() + &state.path.url));
         Shell::clear(state);
         Some(Box::new(Shell::new()))
       }
@@ -77,13 +86,23 @@ impl App for Less {
   fn autocomplete(&self, _state: &TermState) -> Vec<String> {
     vec!['q'.to_string()]
   }
+
+  // keeps the source line at the top of the screen in view
+  fn resize(&mut self, state: &mut TermState) {
+    let top = self.sources.get(self.line).copied().unwrap_or(0);
+    self.reflow(state.width);
+    self.less_from(state, self.shown_line(top));
+  }
 }
 
 impl Less {
   pub fn new() -> Self {
     Self {
       ansi_buffer: vec![],
+      source: vec![],
       lines: vec![],
+      sources: vec![],
+      markdown: false,
       line: 0,
       ansi: false,
     }
@@ -104,7 +123,7 @@ impl Less {
     } else {
       lines_len
     };
-    let head: Vec<&str> = self.lines[n..m].to_vec();
+    let head = &self.lines[n..m];
     let padding = state.height - head.len();
     let suffix = if n == bound {
       Style::new()
@@ -124,28 +143,124 @@ impl Less {
     );
   }
 
-  pub fn less(&mut self, state: &mut TermState, path_str: &str) -> Result<(), String> {
+  // pager for a file, shown once its content is loaded
+  pub fn open(
+    state: &mut TermState,
+    path_str: &str,
+    on_error: impl FnOnce(&mut TermState, String) + 'static,
+  ) -> Option<Box<dyn App>> {
+    Self::open_at(state, path_str, 0, true, on_error)
+  }
+
+  // pager scrolled to `line`, source files are highlighted unless `highlight` is false
+  pub fn open_at(
+    state: &mut TermState,
+    path_str: &str,
+    line: usize,
+    highlight: bool,
+    on_error: impl FnOnce(&mut TermState, String) + 'static,
+  ) -> Option<Box<dyn App>> {
+    let file = match Self::find(state, path_str) {
+      Ok(file) => file,
+      Err(error) => {
+        on_error(state, error);
+        return None;
+      }
+    };
+    pending::spawn(
+      state,
+      async move {
+        let content = file.load().await;
+        (file, content)
+      },
+      move |state, (file, content)| match content {
+        Ok(content) => {
+          let content = if highlight {
+            highlight::highlight(&file.filename, &content).unwrap_or(content)
+          } else {
+            content
+          };
+          let mut less = Less::new();
+          less.less(state, &file, content, line);
+          let app_box: Box<dyn App> = Box::new(less);
+          Some(app_box)
+        }
+        Err(error) => {
+          on_error(state, error);
+          None
+        }
+      },
+    )
+  }
+
+  fn find(state: &TermState, path_str: &str) -> Result<filesystem::Node, String> {
     let path = state.path.join(path_str);
     info!("{}", path);
     let resolved = utils::resolve_path(&path);
     info!("{}", resolved);
-    let change = filesystem::ROOT.get_file(resolved.clone());
+    let change = filesystem::get_file(resolved.clone());
     if !resolved.is_empty() && change.is_ok() {
       let file = change?;
       if file.is_dir {
         return Err(format!("read error: {} Is a directory", path_str));
       }
-      let _ = utils::change_url(&("/".to_string() + file.url));
-      info!("{}", file.url);
-      let content = Box::leak(Box::new(file.load()?));
-      self.lines = content.lines().collect();
-      self.less_from(state, 0);
-      return Ok(());
+      return Ok(file);
     }
 
     Err(format!("{}: No such file", path_str))
   }
 
+  pub fn less(&mut self, state: &mut TermState, file: &filesystem::Node, content: String, line: usize) {
+    let _ = utils::change_url(&("/".to_string() + &file.url));
+    info!("{}", file.url);
+    let content = Box::leak(Box::new(content));
+    self.source = content.lines().collect();
+    self.markdown = file.filename.ends_with(".md");
+    self.reflow(state.width);
+    self.less_from(state, self.shown_line(line));
+  }
+
+  // wraps the lines that don't fit, 0 leaves them as they are
+  fn reflow(&mut self, width: usize) {
+    self.lines.clear();
+    self.sources.clear();
+    let mut index = 0;
+    while index < self.source.len() {
+      let line = self.source[index];
+      let indent = hanging_indent(line);
+      let mut end = index + 1;
+      if self.markdown && width > 0 && width < RENDERED_WIDTH {
+        while end < self.source.len() && continues(self.source[end - 1], self.source[end], &indent) {
+          end += 1;
+        }
+      }
+      if end == index + 1 && (width == 0 || wrap::width(line) <= width) {
+        self.lines.push(line.to_string());
+        self.sources.push(index);
+        index = end;
+        continue;
+      }
+      let mut paragraph = line.to_string();
+      for next in &self.source[index + 1..end] {
+        paragraph.push(' ');
+        paragraph.push_str(&next[indent_prefix(next).len()..]);
+      }
+      let wrapped = wrap::wrap(&paragraph, width, &indent);
+      let count = wrapped.len();
+      for (shown, wrapped) in wrapped.into_iter().enumerate() {
+        self.lines.push(wrapped);
+        // spread the source lines over the shown ones, so resizing keeps roughly the same place
+        self.sources.push(index + shown * (end - index) / count);
+      }
+      index = end;
+    }
+  }
+
+  // first shown line of a source line
+  fn shown_line(&self, source: usize) -> usize {
+    self.sources.partition_point(|line| *line < source)
+  }
+
   fn ansi_clear(&mut self) {
     self.ansi_buffer.clear();
     self.ansi = false;
@@ -190,3 +305,57 @@ impl Less {
     };
   }
 }
+
+// indentation of the lines a line wraps onto, code keeps its indentation, quotes their bar
+// and list items continue after their marker
+fn hanging_indent(line: &str) -> String {
+  let indent = indent_prefix(line);
+  let marker = marker_width(&wrap::strip_ansi(&line[indent.len()..]));
+  format!("{}{}", indent, " ".repeat(marker))
+}
+
+// leading spaces and quote bars with their styles, styles after the last of them belong to the text
+fn indent_prefix(line: &str) -> &str {
+  let mut offset = 0;
+  let mut end = 0;
+  loop {
+    let rest = &line[offset..];
+    let escape = wrap::escape_len(rest);
+    if escape > 0 {
+      offset += escape;
+    } else if rest.starts_with(' ') || rest.starts_with('│') {
+      offset += rest.chars().next().unwrap().len_utf8();
+      end = offset;
+    } else {
+      break;
+    }
+  }
+  &line[..end]
+}
+
+// columns of a list marker the plain text starts with, 0 if it isn't a list item
+fn marker_width(plain: &str) -> usize {
+  let digits = plain.chars().take_while(char::is_ascii_digit).count();
+  if ["• ", "- ", "* "].iter().any(|bullet| plain.starts_with(bullet)) {
+    2
+  } else if digits > 0 && plain[digits..].starts_with(". ") {
+    digits + 2
+  } else {
+    0
+  }
+}
+
+// whether `next` is the rest of the paragraph `previous` belongs to, i.e. the renderer broke the line
+// because the first word of `next` didn't fit anymore, code blocks and tables are left alone
+fn continues(previous: &str, next: &str, indent: &str) -> bool {
+  let previous_width = wrap::strip_ansi(previous).trim_end().chars().count();
+  let prefix = indent_prefix(next);
+  let rest = wrap::strip_ansi(&next[prefix.len()..]);
+  let first_word = rest.split(' ').next().unwrap_or_default().chars().count();
+  previous_width <= RENDERED_WIDTH
+    && previous_width + 1 + first_word > RENDERED_WIDTH
+    && wrap::strip_ansi(prefix) == wrap::strip_ansi(indent)
+    && !rest.trim().is_empty()
+    && marker_width(&rest) == 0
+    && ![previous, next].iter().any(|line| wrap::strip_ansi(&line[indent_prefix(line).len()..]).contains(['│', '─']))
+}
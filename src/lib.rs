@@ -7,17 +7,32 @@ use std::sync::Mutex;
 use cfg_if::cfg_if;
 use cmds::cmds_init;
 use lazy_static::lazy_static;
-use log::info;
+use log::{info, warn};
 use wasm_bindgen::prelude::*;
 
+// only build.rs uses them, the library builds them for their tests
+#[cfg(test)]
+mod ansi;
 mod app;
+mod cache;
 mod cmds;
 mod consts;
 mod filesystem;
+mod highlight;
 mod less;
+mod manifest;
+#[cfg(test)]
+mod markdown;
+mod overlay;
+mod pending;
+mod providers;
+mod search;
 mod shell;
 mod termstate;
+mod text;
 mod utils;
+mod vi;
+mod wrap;
 
 cfg_if! {
     if #[cfg(feature = "console_log")] {
@@ -57,12 +72,27 @@ pub fn readline(input: &str) {
   term.readline(input);
 }
 
+// finishes work a command left pending, called once its future resolved
+fn complete(id: u64, completion: pending::Completion) {
+  let mut term = TERM.lock().unwrap();
+  let term = term.deref_mut();
+  if let Some(app) = pending::complete(id, &mut term.state, completion) {
+    term.app = app;
+  }
+}
+
 #[wasm_bindgen]
 pub fn scroll(lines: i32) {
   let mut term = TERM.lock().unwrap();
   term.scroll(lines);
 }
 
+#[wasm_bindgen]
+pub fn resize(height: usize, width: usize) {
+  let mut term = TERM.lock().unwrap();
+  term.resize(height, width);
+}
+
 #[wasm_bindgen]
 pub fn autocomplete() -> Vec<JsValue> {
   let mut completions: Vec<JsValue> = Vec::new();
@@ -95,36 +125,47 @@ impl Term {
       location_str.pop();
     }
     location_str.remove(0);
-    let path = filesystem::ROOT.get_file(&location_str.clone());
     self.state.width = width;
     self.state.height = height;
+    // chunked builds have to fetch the listings on the way to the location first
+    let app = pending::spawn(
+      &mut self.state,
+      filesystem::prefetch(vec![location_str.clone()]),
+      move |state, ()| Term::open(state, location_str),
+    );
+    self.app = app.unwrap_or_else(|| Box::new(shell::Shell::new()));
+  }
+
+  // shows the directory or file the page was opened at
+  fn open(state: &mut termstate::TermState, location_str: String) -> Option<Box<dyn app::App>> {
+    let path = filesystem::get_file(&location_str.clone());
     if path.is_ok() {
       if path.clone().unwrap().is_dir {
-        self.state.path = path.unwrap();
+        state.path = path.unwrap();
       } else {
-        self.state.path = &mut filesystem::ROOT
-          .get_file(&(location_str.clone() + "/.."))
+        state.path = filesystem::get_file(&(location_str.clone() + "/.."))
           .unwrap();
-        let mut less_app = less::Less::new();
         let offset = match location_str.rfind("/") {
           Some(off) => off + 1,
           None => 0,
         };
-        let filename = &location_str[offset..];
+        let filename = location_str[offset..].to_string();
         info!("opening file {}", filename);
-        less_app.less(&mut self.state, filename).unwrap();
-        self.app = Box::new(less_app);
-        return;
+        return less::Less::open(state, &filename, move |state, error| {
+          warn!("failed to open {}: {}", location_str, error);
+          shell::Shell::clear(state);
+        });
       }
     }
-    self.app = Box::new(shell::Shell::new());
-    shell::Shell::clear(&mut self.state);
+    shell::Shell::clear(state);
+    None
   }
 
   pub fn readline(&mut self, input: &str) {
     for c in input.chars() {
       self.readchar(c);
     }
+    self.app.input_end(self.state.deref_mut());
   }
 
   pub fn autocomplete(&mut self) -> Vec<String> {
@@ -136,6 +177,17 @@ impl Term {
     self.app.scroll(self.state.deref_mut(), lines);
   }
 
+  // the running app redraws for the new size, e.g. less reflows its file
+  pub fn resize(&mut self, height: usize, width: usize) {
+    if !self.init {
+      return;
+    }
+    info!("resize {}x{}", width, height);
+    self.state.height = height;
+    self.state.width = width;
+    self.app.resize(self.state.deref_mut());
+  }
+
   fn readchar(&mut self, input: char) {
     let x = self.state.cursor_x;
     let y = self.state.cursor_y;
@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::filesystem::{self, Entry, Meta};
+use crate::utils::{self, Fetched, Validators};
+
+// listing build.rs writes into every directory of chunked builds
+const MANIFEST_NAME: &str = ".manifest.ron";
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+  filename: String,
+  size: u64,
+  mode: u32,
+  nlink: u64,
+  owner: String,
+  group: String,
+  modified: u64,
+  created: u64,
+  is_dir: bool,
+  link: Option<String>,
+  sha256: Option<String>,
+  meta: Option<ManifestMeta>,
+}
+
+#[derive(Deserialize)]
+struct ManifestMeta {
+  title: Option<String>,
+  date: Option<u64>,
+  tags: Vec<String>,
+  summary: Option<String>,
+  draft: bool,
+}
+
+lazy_static! {
+  // directory url -> its entries, kept for the whole session
+  static ref LOADED: Mutex<HashMap<String, &'static [Entry]>> = Mutex::new(HashMap::new());
+}
+
+// entries of a directory, None until its manifest was loaded
+pub fn entries(url: &str) -> Option<&'static [Entry]> {
+  LOADED.lock().unwrap().get(url).copied()
+}
+
+// fetches the manifest of a directory, failures aren't kept, so the next lookup tries again
+pub async fn load(url: &str) -> Result<(), String> {
+  let manifest_url = format!("{}{}", filesystem::ROOT_URL, child_path(url, MANIFEST_NAME));
+  info!("loading manifest: {}", manifest_url);
+  let mut entries: Vec<Entry> = match fetch(&manifest_url).await {
+    Ok(listed) => listed
+      .into_iter()
+      .map(|listed| entry(url, listed))
+      .collect(),
+    Err(error) => {
+      warn!("{}: {}", manifest_url, error);
+      return Err(error);
+    }
+  };
+  // lookups binary search by name
+  entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+  LOADED
+    .lock()
+    .unwrap()
+    .insert(url.to_string(), Box::leak(entries.into_boxed_slice()));
+  Ok(())
+}
+
+async fn fetch(manifest_url: &str) -> Result<Vec<ManifestEntry>, String> {
+  match utils::afetch(manifest_url, &Validators::default()).await? {
+    Fetched::Content(content, _validators) => ron::from_str(&content).map_err(|error| error.to_string()),
+    Fetched::NotModified => Err("unexpected 304".to_string()),
+  }
+}
+
+fn entry(dir: &str, listed: ManifestEntry) -> Entry {
+  Entry {
+    url: Cow::Owned(child_path(dir, &listed.filename)),
+    filename: Cow::Owned(listed.filename),
+    size: listed.size,
+    mode: listed.mode,
+    nlink: listed.nlink,
+    owner: leak(listed.owner),
+    group: leak(listed.group),
+    modified: listed.modified,
+    created: listed.created,
+    is_dir: listed.is_dir,
+    link: listed.link.map(leak),
+    sha256: listed.sha256.map(leak),
+    meta: listed.meta.map(|meta| Meta {
+      title: meta.title.map(leak),
+      date: meta.date,
+      tags: Box::leak(meta.tags.into_iter().map(leak).collect()),
+      summary: meta.summary.map(leak),
+      draft: meta.draft,
+    }),
+    provider: None,
+    content: None,
+    entries: &[],
+  }
+}
+
+// listings stay for the whole session, like the static tree
+fn leak(text: String) -> &'static str {
+  Box::leak(text.into_boxed_str())
+}
+
+fn child_path(dir: &str, name: &str) -> String {
+  if dir.is_empty() {
+    name.to_string()
+  } else {
+    format!("{}/{}", dir, name)
+  }
+}
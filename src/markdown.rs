@@ -0,0 +1,416 @@
+// markdown to ANSI for the terminal, build.rs includes this file next to wrap.rs and renders raw posts with it
+// the style follows the glow output the posts were rendered with before, 60 columns wide with a 2 column margin
+
+use pulldown_cmark::{Alignment, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+use crate::wrap;
+
+const WIDTH: usize = 60;
+const MARGIN: &str = "  ";
+
+const RESET: &str = "\x1b[0m";
+const H1: &str = "\x1b[1;38;5;228;48;5;63m";
+const HEADING: &str = "\x1b[1;38;5;39m";
+const BOLD: &str = "\x1b[1m";
+const ITALIC: &str = "\x1b[3m";
+const STRIKE: &str = "\x1b[9m";
+const CODE: &str = "\x1b[38;5;203;48;5;236m";
+const CODE_BLOCK: &str = "\x1b[38;5;251m";
+const LINK: &str = "\x1b[4;38;5;30m";
+const IMAGE: &str = "\x1b[38;5;212m";
+const QUOTE: &str = "\x1b[38;5;240m";
+const RULE: &str = "\x1b[38;5;240m";
+const BULLET: &str = "\x1b[38;5;246m";
+const LINK_END: &str = "\x1b]8;;\x1b\\";
+
+// a container the lines are nested in, e.g. a block quote or a list item
+struct Prefix {
+  first: String,
+  rest: String,
+  used: bool,
+}
+
+#[derive(Default)]
+struct Table {
+  alignments: Vec<Alignment>,
+  rows: Vec<Vec<String>>,
+  head: bool,
+}
+
+#[derive(Default)]
+struct Renderer {
+  lines: Vec<String>,
+  // text of the current paragraph, heading, item or cell
+  inline: String,
+  // style the inline text starts with, e.g. the heading color
+  base: &'static str,
+  // inline styles that are open, restored after every nested one closes
+  styles: Vec<&'static str>,
+  prefixes: Vec<Prefix>,
+  // next number of every open list, None for bullet lists
+  lists: Vec<Option<u64>>,
+  code: Option<String>,
+  table: Option<Table>,
+  // a blank line goes before the next block
+  separate: bool,
+}
+
+// the body of a markdown file rendered for the terminal
+pub fn render(markdown: &str) -> String {
+  let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+  let mut renderer = Renderer::default();
+  for event in Parser::new_ext(markdown, options) {
+    renderer.event(event);
+  }
+  renderer.flush();
+  let mut rendered = renderer.lines.join("\n");
+  rendered.push('\n');
+  rendered
+}
+
+impl Renderer {
+  fn event(&mut self, event: Event) {
+    match event {
+      Event::Start(tag) => self.start(tag),
+      Event::End(tag) => self.end(tag),
+      Event::Text(content) => match &mut self.code {
+        Some(code) => code.push_str(&content),
+        None => self.inline.push_str(&content),
+      },
+      Event::Code(content) => {
+        self.inline.push_str(CODE);
+        self.inline.push_str(&content);
+        self.restore();
+      }
+      Event::Html(html) => {
+        for line in html.trim_end_matches('\n').lines() {
+          self.push_line(line.to_string());
+        }
+      }
+      Event::InlineHtml(html) => self.inline.push_str(&html),
+      Event::SoftBreak => self.inline.push(' '),
+      Event::HardBreak => self.inline.push('\n'),
+      Event::Rule => {
+        self.block();
+        let width = WIDTH.saturating_sub(self.prefix_width());
+        self.push_line(format!("{}{}{}", RULE, "─".repeat(width), RESET));
+        self.separate = true;
+      }
+      Event::TaskListMarker(checked) => self.inline.push_str(if checked { "[✓] " } else { "[ ] " }),
+      _ => {}
+    }
+  }
+
+  fn start(&mut self, tag: Tag) {
+    match tag {
+      Tag::Paragraph => self.block(),
+      Tag::Heading { level, .. } => {
+        self.block();
+        let (base, marker) = match level {
+          HeadingLevel::H1 => (H1, " ".to_string()),
+          level => (HEADING, format!("{} ", "#".repeat(level as usize))),
+        };
+        self.base = base;
+        self.inline.push_str(base);
+        self.inline.push_str(&marker);
+      }
+      Tag::BlockQuote(_) => {
+        self.block();
+        let bar = format!("{}│{} ", QUOTE, RESET);
+        self.prefixes.push(Prefix {
+          first: bar.clone(),
+          rest: bar,
+          used: false,
+        });
+      }
+      Tag::CodeBlock(_) | Tag::HtmlBlock => {
+        self.block();
+        self.code = matches!(tag, Tag::CodeBlock(_)).then(String::new);
+      }
+      Tag::List(start) => {
+        // a nested list ends the text of its item
+        self.flush();
+        if self.lists.is_empty() {
+          self.block();
+        }
+        self.lists.push(start);
+      }
+      Tag::Item => {
+        let marker = match self.lists.last_mut() {
+          Some(Some(number)) => {
+            *number += 1;
+            format!("{}. ", *number - 1)
+          }
+          _ => "• ".to_string(),
+        };
+        let width = marker.chars().count();
+        self.prefixes.push(Prefix {
+          first: format!("{}{}{}", BULLET, marker, RESET),
+          rest: " ".repeat(width),
+          used: false,
+        });
+      }
+      Tag::Table(alignments) => {
+        self.block();
+        self.table = Some(Table {
+          alignments,
+          ..Table::default()
+        });
+      }
+      Tag::TableHead => {
+        if let Some(table) = &mut self.table {
+          table.head = true;
+          table.rows.push(vec![]);
+        }
+      }
+      Tag::TableRow => {
+        if let Some(table) = &mut self.table {
+          table.rows.push(vec![]);
+        }
+      }
+      Tag::Emphasis => self.open(ITALIC),
+      Tag::Strong => self.open(BOLD),
+      Tag::Strikethrough => self.open(STRIKE),
+      Tag::Link { dest_url, .. } => {
+        self.inline.push_str(&format!("\x1b]8;;{}\x1b\\", dest_url));
+        self.open(LINK);
+      }
+      Tag::Image { dest_url, .. } => {
+        self.inline.push_str(&format!("\x1b]8;;{}\x1b\\", dest_url));
+        self.open(IMAGE);
+        self.inline.push_str("Image: ");
+      }
+      _ => {}
+    }
+  }
+
+  fn end(&mut self, tag: TagEnd) {
+    match tag {
+      TagEnd::Paragraph => {
+        self.flush();
+        self.separate = true;
+      }
+      TagEnd::Heading(_) => {
+        if self.base == H1 {
+          self.inline.push(' ');
+        }
+        self.inline.push_str(RESET);
+        self.base = "";
+        self.flush();
+        self.separate = true;
+      }
+      TagEnd::BlockQuote(_) => {
+        self.flush();
+        self.prefixes.pop();
+        self.separate = true;
+      }
+      TagEnd::HtmlBlock => self.separate = true,
+      TagEnd::CodeBlock => {
+        let code = self.code.take().unwrap_or_default();
+        for line in code.trim_end_matches('\n').lines() {
+          self.push_line(format!("{}{}{}{}", MARGIN, CODE_BLOCK, line, RESET));
+        }
+        self.separate = true;
+      }
+      TagEnd::List(_) => {
+        self.flush();
+        self.lists.pop();
+        // nested lists continue their item without a gap
+        self.separate = self.lists.is_empty();
+      }
+      TagEnd::Item => {
+        self.flush();
+        self.prefixes.pop();
+      }
+      TagEnd::TableCell => {
+        let cell = std::mem::take(&mut self.inline);
+        if let Some(row) = self.table.as_mut().and_then(|table| table.rows.last_mut()) {
+          row.push(cell.trim().to_string());
+        }
+      }
+      TagEnd::Table => {
+        if let Some(table) = self.table.take() {
+          for line in table.render() {
+            self.push_line(line);
+          }
+        }
+        self.separate = true;
+      }
+      TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => self.close(),
+      TagEnd::Link | TagEnd::Image => {
+        self.close();
+        self.inline.push_str(LINK_END);
+      }
+      _ => {}
+    }
+  }
+
+  fn open(&mut self, style: &'static str) {
+    self.styles.push(style);
+    self.inline.push_str(style);
+  }
+
+  fn close(&mut self) {
+    self.styles.pop();
+    self.restore();
+  }
+
+  // back to the styles that are still open
+  fn restore(&mut self) {
+    self.inline.push_str(RESET);
+    self.inline.push_str(self.base);
+    for style in &self.styles {
+      self.inline.push_str(style);
+    }
+  }
+
+  // a blank line between this block and the previous one
+  fn block(&mut self) {
+    self.flush();
+    if self.separate && !self.lines.is_empty() {
+      let blank: String = self.prefixes.iter().map(|container| container.rest.as_str()).collect();
+      self.lines.push(format!("{}{}", MARGIN, blank).trim_end().to_string());
+    }
+    self.separate = false;
+  }
+
+  // wraps the pending inline text into lines
+  fn flush(&mut self) {
+    let inline = std::mem::take(&mut self.inline);
+    if wrap::strip_ansi(&inline).trim().is_empty() {
+      return;
+    }
+    let width = WIDTH.saturating_sub(self.prefix_width()).max(20);
+    for line in wrap::wrap(&inline, width, "") {
+      self.push_line(line);
+    }
+  }
+
+  fn push_line(&mut self, line: String) {
+    let prefix = self.prefix();
+    self.lines.push(format!("{}{}", prefix, line).trim_end().to_string());
+  }
+
+  // margin and container prefixes, the first line of an item shows its marker
+  fn prefix(&mut self) -> String {
+    let mut prefix = MARGIN.to_string();
+    for container in &mut self.prefixes {
+      if container.used {
+        prefix.push_str(&container.rest);
+      } else {
+        prefix.push_str(&container.first);
+        container.used = true;
+      }
+    }
+    prefix
+  }
+
+  fn prefix_width(&self) -> usize {
+    MARGIN.len() + self.prefixes.iter().map(|container| wrap::width(&container.rest)).sum::<usize>()
+  }
+}
+
+impl Table {
+  fn render(&self) -> Vec<String> {
+    let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in &self.rows {
+      for (column, cell) in row.iter().enumerate() {
+        widths[column] = widths[column].max(wrap::width(cell));
+      }
+    }
+    let mut lines = vec![];
+    for (index, row) in self.rows.iter().enumerate() {
+      let cells: Vec<String> = (0..columns)
+        .map(|column| {
+          let cell = row.get(column).map_or("", String::as_str);
+          let padding = widths[column] - wrap::width(cell);
+          let cell = if index == 0 && self.head {
+            format!("{}{}{}", BOLD, cell, RESET)
+          } else {
+            cell.to_string()
+          };
+          match self.alignments.get(column) {
+            Some(Alignment::Right) => format!("{}{}", " ".repeat(padding), cell),
+            Some(Alignment::Center) => format!("{}{}{}", " ".repeat(padding / 2), cell, " ".repeat(padding - padding / 2)),
+            _ => format!("{}{}", cell, " ".repeat(padding)),
+          }
+        })
+        .collect();
+      lines.push(cells.join(&format!(" {}│{} ", RULE, RESET)));
+      if index == 0 && self.head {
+        let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+        lines.push(format!("{}{}{}", RULE, rule.join("─┼─"), RESET));
+      }
+    }
+    lines
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // rendered lines without their styles
+  fn plain(markdown: &str) -> Vec<String> {
+    render(markdown).lines().map(wrap::strip_ansi).collect()
+  }
+
+  #[test]
+  fn paragraphs_wrap_within_the_width() {
+    let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
+                     incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam.";
+    let lines = plain(paragraph);
+    assert!(lines.len() > 1);
+    for line in &lines {
+      assert!(line.starts_with(MARGIN));
+      assert!(line.chars().count() <= WIDTH, "{:?} is too wide", line);
+    }
+    let words: Vec<&str> = lines.iter().flat_map(|line| line.split_whitespace()).collect();
+    assert_eq!(words, paragraph.split_whitespace().collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn blocks_are_separated_by_blank_lines() {
+    assert_eq!(plain("first\n\nsecond"), ["  first", "", "  second"]);
+    assert_eq!(plain("## Title\ntext"), ["  ## Title", "", "  text"]);
+  }
+
+  #[test]
+  fn list_items_hang_after_their_marker() {
+    assert_eq!(plain("- one\n- two"), ["  • one", "  • two"]);
+    assert_eq!(plain("3. three\n4. four"), ["  3. three", "  4. four"]);
+    let item = format!("- {}", "word ".repeat(15));
+    let lines = plain(&item);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("  • word"));
+    assert!(lines[1].starts_with("    word"));
+  }
+
+  #[test]
+  fn quotes_and_code_blocks() {
+    assert_eq!(plain("> quoted"), ["  │ quoted"]);
+    assert_eq!(plain("```\nlet x = 1;\n  indented\n```"), ["    let x = 1;", "      indented"]);
+  }
+
+  #[test]
+  fn tables_are_aligned() {
+    let table = "| name | n |\n|:-----|--:|\n| long cell | 1 |\n| x | 22 |";
+    assert_eq!(
+      plain(table),
+      [
+        "  name      │  n",
+        "  ──────────┼───",
+        "  long cell │  1",
+        "  x         │ 22",
+      ]
+    );
+  }
+
+  #[test]
+  fn links_keep_their_target() {
+    let rendered = render("[site](https://example.com)");
+    assert!(rendered.contains("\x1b]8;;https://example.com\x1b\\"));
+    assert_eq!(wrap::strip_ansi(&rendered), "  site\n");
+  }
+}
@@ -0,0 +1,411 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::filesystem::{self, Content, Entry, Node};
+use crate::utils;
+
+// localStorage key the session changes are persisted under
+const STORAGE_KEY: &str = "fs-overlay";
+const OWNER: &str = "visitor";
+// in-memory directory that is dropped with the session instead of being persisted
+const TMP_DIR: &str = "tmp";
+
+// change made on top of the static tree, keyed by url
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Upper {
+  Dir { modified: u64 },
+  File { content: String, modified: u64 },
+  // unmodified copy of a static file, its content is only fetched when read
+  Copy { source: String, modified: u64 },
+  // removed static entry
+  Whiteout,
+}
+
+lazy_static! {
+  static ref UPPER: Mutex<BTreeMap<String, Upper>> = Mutex::new(restore());
+}
+
+fn restore() -> BTreeMap<String, Upper> {
+  let mut upper = match utils::storage_get(STORAGE_KEY) {
+    Some(serialized) => ron::from_str(&serialized).unwrap_or_else(|error| {
+      warn!("discarding corrupt overlay: {}", error);
+      BTreeMap::new()
+    }),
+    None => BTreeMap::new(),
+  };
+  mount_tmp(&mut upper);
+  upper
+}
+
+fn mount_tmp(upper: &mut BTreeMap<String, Upper>) {
+  if filesystem::get_lower(TMP_DIR).is_none() {
+    upper.insert(TMP_DIR.to_string(), Upper::Dir { modified: now() });
+  }
+}
+
+pub fn is_tmp(path: &str) -> bool {
+  path == TMP_DIR || path.starts_with(&format!("{}/", TMP_DIR))
+}
+
+// changes that outlive the session, everything but /tmp
+fn persistent(upper: &BTreeMap<String, Upper>) -> BTreeMap<&String, &Upper> {
+  upper.iter().filter(|(path, _upper)| !is_tmp(path)).collect()
+}
+
+fn persist(upper: &BTreeMap<String, Upper>) {
+  match ron::to_string(&persistent(upper)) {
+    Ok(serialized) => utils::storage_set(STORAGE_KEY, &serialized),
+    Err(error) => warn!("failed to persist overlay: {}", error),
+  }
+}
+
+pub fn get(path: &str) -> Option<Upper> {
+  UPPER.lock().unwrap().get(path).cloned()
+}
+
+// direct children of a directory, including whiteouts
+pub fn list(dir: &str) -> Vec<(String, Upper)> {
+  let prefix = if dir.is_empty() {
+    String::new()
+  } else {
+    format!("{}/", dir)
+  };
+  UPPER
+    .lock()
+    .unwrap()
+    .range(prefix.clone()..)
+    .take_while(|(path, _upper)| path.starts_with(&prefix))
+    .filter(|(path, _upper)| !path[prefix.len()..].contains('/'))
+    .map(|(path, upper)| (path.clone(), upper.clone()))
+    .collect()
+}
+
+fn insert(changes: Vec<(String, Upper)>) {
+  let mut overlay = UPPER.lock().unwrap();
+  for (path, upper) in changes {
+    info!("overlay insert {}", path);
+    overlay.insert(path, upper);
+  }
+  persist(&overlay);
+}
+
+// drop a path and everything below it, static entries are hidden by a whiteout
+pub fn remove(path: &str) {
+  info!("overlay remove {}", path);
+  let mut overlay = UPPER.lock().unwrap();
+  let prefix = format!("{}/", path);
+  overlay.retain(|key, _upper| key != path && !key.starts_with(&prefix));
+  if filesystem::get_lower(path).is_some() {
+    overlay.insert(path.to_string(), Upper::Whiteout);
+  }
+  persist(&overlay);
+}
+
+// url a new entry at a resolved path gets, its parent has to be an existing directory
+fn target_url(path: &str) -> Result<String, String> {
+  let (parent, name) = match path.rfind('/') {
+    Some(off) => (&path[..off], &path[off + 1..]),
+    None => ("", path),
+  };
+  if name.is_empty() {
+    return Err("Invalid path".to_string());
+  }
+  let dir = filesystem::get_file(parent).map_err(|_| "No such file or directory".to_string())?;
+  if !dir.is_dir {
+    return Err("Not a directory".to_string());
+  }
+  Ok(dir.child_path(name))
+}
+
+pub fn mkdir(path: &str) -> Result<(), String> {
+  if filesystem::get_file_nofollow(path).is_ok() {
+    return Err("File exists".to_string());
+  }
+  insert(vec![(target_url(path)?, Upper::Dir { modified: now() })]);
+  Ok(())
+}
+
+// replace or extend the content of a file, creating it if needed
+pub fn write(path: &str, content: &str, append: bool) -> Result<(), String> {
+  let (url, content) = match filesystem::get_file(path) {
+    Ok(file) if file.is_dir => return Err("Is a directory".to_string()),
+    // generated files like /dev/null swallow everything
+    Ok(file) if file.provider.is_some() => return Ok(()),
+    // static files have to be loaded before they can be appended to
+    Ok(file) if append => match file.local_content() {
+      Some(existing) => (file.url.to_string(), existing? + content),
+      None => return Err("Resource temporarily unavailable".to_string()),
+    },
+    Ok(file) => (file.url.to_string(), content.to_string()),
+    Err(_) => (target_url(path)?, content.to_string()),
+  };
+  insert(vec![(url, Upper::File { content, modified: now() })]);
+  Ok(())
+}
+
+// extends a file like write, static content that isn't in memory is loaded and copied up first
+pub async fn append(path: &str, content: &str) -> Result<(), String> {
+  if let Ok(file) = filesystem::get_file(path) {
+    if !file.is_dir && file.provider.is_none() && file.local_content().is_none() {
+      let existing = file.load().await?;
+      write(&file.url, &existing, false)?;
+    }
+  }
+  write(path, content, true)
+}
+
+pub fn touch(path: &str) -> Result<(), String> {
+  let modified = now();
+  let (url, upper) = match filesystem::get_file(path) {
+    Ok(Node::Upper(entry)) => {
+      let upper = match get(&entry.url) {
+        Some(Upper::Dir { .. }) => Upper::Dir { modified },
+        Some(Upper::File { content, .. }) => Upper::File { content, modified },
+        Some(Upper::Copy { source, .. }) => Upper::Copy { source, modified },
+        _ => return Ok(()),
+      };
+      (entry.url.to_string(), upper)
+    }
+    // static files get copied up with a new timestamp
+    Ok(Node::Lower(entry)) if !entry.is_dir => (
+      entry.url.to_string(),
+      Upper::Copy {
+        source: entry.url.to_string(),
+        modified,
+      },
+    ),
+    Ok(Node::Lower(_)) => return Ok(()),
+    Err(_) => (
+      target_url(path)?,
+      Upper::File {
+        content: String::new(),
+        modified,
+      },
+    ),
+  };
+  insert(vec![(url, upper)]);
+  Ok(())
+}
+
+pub fn copy(source: &Node, path: &str) -> Result<(), String> {
+  let url = target_url(path)?;
+  if url == source.url || url.starts_with(&format!("{}/", source.url)) {
+    return Err("cannot copy a directory into itself".to_string());
+  }
+  let mut changes = vec![];
+  copy_rec(source, url, &mut changes);
+  insert(changes);
+  Ok(())
+}
+
+fn copy_rec(source: &Node, url: String, changes: &mut Vec<(String, Upper)>) {
+  let modified = now();
+  if source.is_dir {
+    changes.push((url.clone(), Upper::Dir { modified }));
+    for (name, child) in source.children() {
+      // copies follow links, broken ones are skipped
+      let child = match child.link {
+        Some(_) => match filesystem::get_file(source.join(name.as_str())) {
+          Ok(target) => target,
+          Err(_) => continue,
+        },
+        None => child,
+      };
+      copy_rec(&child, format!("{}/{}", url, name), changes);
+    }
+    return;
+  }
+  let upper = match (source, &source.content) {
+    (Node::Upper(_), Some(Content::Text(content))) => Upper::File {
+      content: content.clone(),
+      modified,
+    },
+    (Node::Upper(_), Some(Content::Lower(entry))) => Upper::Copy {
+      source: entry.url.to_string(),
+      modified,
+    },
+    _ => Upper::Copy {
+      source: source.url.to_string(),
+      modified,
+    },
+  };
+  changes.push((url, upper));
+}
+
+pub fn reset() {
+  let mut overlay = UPPER.lock().unwrap();
+  overlay.clear();
+  mount_tmp(&mut overlay);
+  utils::storage_remove(STORAGE_KEY);
+}
+
+pub fn now() -> u64 {
+  chrono::Utc::now().timestamp() as u64
+}
+
+// entry of the merged view for an overlay change, whiteouts have none
+pub fn node(path: &str, upper: &Upper) -> Option<Node> {
+  let filename = match path.rfind('/') {
+    Some(off) => &path[off + 1..],
+    None => path,
+  };
+  let (is_dir, size, modified, content) = match upper {
+    Upper::Dir { modified } => (true, 0, *modified, None),
+    Upper::File { content, modified } => (
+      false,
+      content.len() as u64,
+      *modified,
+      Some(Content::Text(content.clone())),
+    ),
+    Upper::Copy { source, modified } => {
+      let lower = filesystem::get_lower(source)?;
+      (false, lower.size, *modified, Some(Content::Lower(lower)))
+    }
+    Upper::Whiteout => return None,
+  };
+  Some(Node::Upper(Arc::new(Entry {
+    filename: Cow::Owned(filename.to_string()),
+    url: Cow::Owned(path.to_string()),
+    size,
+    mode: if is_dir { 0o755 } else { 0o644 },
+    nlink: if is_dir { 2 } else { 1 },
+    owner: OWNER,
+    group: OWNER,
+    modified,
+    created: modified,
+    is_dir,
+    link: None,
+    sha256: None,
+    meta: None,
+    provider: None,
+    content,
+    entries: &[],
+  })))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the overlay is shared, so its tests take turns and start from a clean one
+  static SERIAL: Mutex<()> = Mutex::new(());
+
+  fn clean() -> std::sync::MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    reset();
+    guard
+  }
+
+  // some file of the static tree, whatever the site content is
+  fn static_file() -> Option<&'static Entry> {
+    let mut dirs = vec![&filesystem::ROOT];
+    while let Some(dir) = dirs.pop() {
+      for entry in dir.entries {
+        if entry.link.is_some() || is_tmp(&entry.url) {
+          continue;
+        }
+        if !entry.is_dir {
+          return Some(entry);
+        }
+        dirs.push(entry);
+      }
+    }
+    None
+  }
+
+  fn content(path: &str) -> Option<String> {
+    filesystem::get_file(path).ok()?.local_content()?.ok()
+  }
+
+  #[test]
+  fn written_files_read_back() {
+    let _guard = clean();
+    write("tmp/a.txt", "hello", false).unwrap();
+    assert_eq!(content("tmp/a.txt").as_deref(), Some("hello"));
+    write("tmp/a.txt", " world", true).unwrap();
+    assert_eq!(content("tmp/a.txt").as_deref(), Some("hello world"));
+    write("tmp/a.txt", "bye", false).unwrap();
+    assert_eq!(content("tmp/a.txt").as_deref(), Some("bye"));
+    assert_eq!(write("tmp/missing/a.txt", "", false), Err("No such file or directory".to_string()));
+    assert_eq!(write("tmp", "", false), Err("Is a directory".to_string()));
+  }
+
+  #[test]
+  fn removed_static_files_can_be_recreated() {
+    let _guard = clean();
+    let Some(file) = static_file() else {
+      return;
+    };
+    remove(&file.url);
+    assert!(matches!(get(&file.url), Some(Upper::Whiteout)));
+    assert!(filesystem::get_file(file.url.as_ref()).is_err());
+    write(&file.url, "new", false).unwrap();
+    assert_eq!(content(&file.url).as_deref(), Some("new"));
+    // removing it again hides the static file once more
+    remove(&file.url);
+    assert!(matches!(get(&file.url), Some(Upper::Whiteout)));
+  }
+
+  #[test]
+  fn touching_static_files_copies_them_up() {
+    let _guard = clean();
+    let Some(file) = static_file() else {
+      return;
+    };
+    touch(&file.url).unwrap();
+    assert!(matches!(get(&file.url), Some(Upper::Copy { source, .. }) if source == file.url));
+    let node = filesystem::get_file(file.url.as_ref()).unwrap();
+    assert!(matches!(node.content, Some(Content::Lower(lower)) if std::ptr::eq(lower, file)));
+    assert_eq!(node.size, file.size);
+    touch("tmp/new").unwrap();
+    assert_eq!(content("tmp/new").as_deref(), Some(""));
+  }
+
+  #[test]
+  fn copies_between_static_files_and_the_overlay() {
+    let _guard = clean();
+    let Some(file) = static_file() else {
+      return;
+    };
+    // static to overlay is a copy-up that fetches the content when it is read
+    copy(&Node::Lower(file), "tmp/copy").unwrap();
+    assert!(matches!(get("tmp/copy"), Some(Upper::Copy { source, .. }) if source == file.url));
+    // copies of a copy point at the static file too
+    copy(&filesystem::get_file("tmp/copy").unwrap(), "tmp/copy2").unwrap();
+    assert!(matches!(get("tmp/copy2"), Some(Upper::Copy { source, .. }) if source == file.url));
+    write("tmp/a.txt", "hello", false).unwrap();
+    copy(&filesystem::get_file("tmp/a.txt").unwrap(), "tmp/b.txt").unwrap();
+    assert_eq!(content("tmp/b.txt").as_deref(), Some("hello"));
+  }
+
+  #[test]
+  fn moved_static_files_keep_their_content() {
+    let _guard = clean();
+    let Some(file) = static_file() else {
+      return;
+    };
+    // mv is a copy and a remove
+    copy(&Node::Lower(file), "tmp/moved").unwrap();
+    remove(&file.url);
+    assert!(filesystem::get_file(file.url.as_ref()).is_err());
+    let moved = filesystem::get_file("tmp/moved").unwrap();
+    assert!(matches!(moved.content, Some(Content::Lower(lower)) if std::ptr::eq(lower, file)));
+  }
+
+  #[test]
+  fn tmp_isnt_persisted() {
+    let upper = BTreeMap::from([
+      ("tmp".to_string(), Upper::Dir { modified: 0 }),
+      ("tmp/a".to_string(), Upper::File { content: String::new(), modified: 0 }),
+      ("tmpfile".to_string(), Upper::File { content: String::new(), modified: 0 }),
+      ("posts/a.md".to_string(), Upper::Whiteout),
+    ]);
+    let persisted: Vec<&String> = persistent(&upper).into_keys().collect();
+    assert_eq!(persisted, ["posts/a.md", "tmpfile"]);
+  }
+}
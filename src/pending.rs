@@ -0,0 +1,136 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use lazy_static::lazy_static;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::app::App;
+use crate::shell::Shell;
+use crate::termstate::TermState;
+use crate::{consts, init, new, prefix, utils, write, writeln_buf};
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+// loads finishing faster than this never show the spinner
+const SPINNER_DELAY: i32 = 100;
+const SPINNER_INTERVAL: i32 = 80;
+
+pub type Completion = Box<dyn FnOnce(&mut TermState) -> Option<Box<dyn App>>>;
+type Hook = Box<dyn FnOnce(&mut TermState) + Send>;
+
+struct Task {
+  id: u64,
+  spinner: bool,
+  // run once the task is done or cancelled
+  hooks: Vec<Hook>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+  static ref ACTIVE: Mutex<Option<Task>> = Mutex::new(None);
+}
+
+// app in front while a command waits for its future, ctrl-c cancels it
+pub struct Pending {
+  id: u64,
+}
+
+impl App for Pending {
+  fn readchar(&mut self, state: &mut TermState, input: char) -> Option<Box<dyn App>> {
+    // ctrl-c
+    if input != '\x03' {
+      return None;
+    }
+    let task = take(self.id)?;
+    write!("^C");
+    init!(state);
+    for hook in task.hooks {
+      hook(state);
+    }
+    Some(Box::new(Shell::new()))
+  }
+
+  fn autocomplete(&self, _state: &TermState) -> Vec<String> {
+    vec![]
+  }
+}
+
+// hands the output of `future` to `then`, right away if it doesn't have to wait
+pub fn spawn<T: 'static>(
+  state: &mut TermState,
+  future: impl Future<Output = T> + 'static,
+  then: impl FnOnce(&mut TermState, T) -> Option<Box<dyn App>> + 'static,
+) -> Option<Box<dyn App>> {
+  let mut future = Box::pin(future);
+  if let Poll::Ready(value) = future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+    return then(state, value);
+  }
+  let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+  *ACTIVE.lock().unwrap() = Some(Task {
+    id,
+    spinner: false,
+    hooks: vec![],
+  });
+  spawn_local(async move {
+    let value = future.await;
+    crate::complete(id, Box::new(move |state| then(state, value)));
+  });
+  spawn_local(spin(id));
+  Some(Box::new(Pending { id }))
+}
+
+// runs the completion of a task, None if it was cancelled in the meantime
+pub fn complete(id: u64, state: &mut TermState, completion: Completion) -> Option<Box<dyn App>> {
+  let task = take(id)?;
+  let app = completion(state).unwrap_or_else(|| Box::new(Shell::new()));
+  // the completion may have started the next task
+  for hook in task.hooks {
+    defer(state, hook);
+  }
+  Some(app)
+}
+
+// run `hook` once the active task is over, immediately if there is none
+pub fn defer(state: &mut TermState, hook: impl FnOnce(&mut TermState) + Send + 'static) {
+  let mut active = ACTIVE.lock().unwrap();
+  match active.as_mut() {
+    Some(task) => task.hooks.push(Box::new(hook)),
+    None => {
+      drop(active);
+      hook(state);
+    }
+  }
+}
+
+fn take(id: u64) -> Option<Task> {
+  let mut active = ACTIVE.lock().unwrap();
+  if active.as_ref().is_none_or(|task| task.id != id) {
+    return None;
+  }
+  let task = active.take()?;
+  if task.spinner {
+    write!("{}", consts::RETURN);
+  }
+  Some(task)
+}
+
+async fn spin(id: u64) {
+  utils::sleep(SPINNER_DELAY).await;
+  for frame in SPINNER.iter().cycle() {
+    {
+      let mut active = ACTIVE.lock().unwrap();
+      let Some(task) = active.as_mut().filter(|task| task.id == id) else {
+        return;
+      };
+      if task.spinner {
+        write!("{}{}", consts::LEFT, frame);
+      } else {
+        write!("{}", frame);
+      }
+      task.spinner = true;
+    }
+    utils::sleep(SPINNER_INTERVAL).await;
+  }
+}
@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+
+use crate::cmds::CMD_HISTORY;
+use crate::filesystem::{self, Entry};
+
+// files whose content is generated by the provider each time they are read
+pub type Provider = fn() -> String;
+
+const PROVIDERS: &[(&str, Provider)] = &[
+  ("/proc/version", version),
+  ("/proc/uptime", uptime),
+  ("/proc/self/history", history),
+  ("/dev/null", null),
+  ("/dev/zero", zero),
+  ("/dev/random", random),
+];
+
+// amount of bytes a single read of an endless device returns
+const DEVICE_READ_SIZE: usize = 256;
+
+lazy_static! {
+  static ref BOOT_TIME: DateTime<Utc> = Utc::now();
+  static ref TREE: Entry = virtual_dir("", "", BOOT_TIME.timestamp() as u64);
+}
+
+// provided files and their parent directories, unioned into the root
+pub fn root() -> &'static Entry {
+  &TREE
+}
+
+fn virtual_dir(filename: &'static str, url: &'static str, modified: u64) -> Entry {
+  let prefix = if url.is_empty() {
+    "/".to_string()
+  } else {
+    format!("/{}/", url)
+  };
+  let mut entries: Vec<Entry> = vec![];
+  for (path, provider) in PROVIDERS {
+    let path: &'static str = path;
+    let Some(rest) = path.strip_prefix(prefix.as_str()) else {
+      continue;
+    };
+    let name = rest.split('/').next().unwrap_or(rest);
+    if entries.iter().any(|entry| entry.filename == name) {
+      continue;
+    }
+    let child_url = &path[1..prefix.len() + name.len()];
+    entries.push(if name == rest {
+      virtual_entry(name, child_url, modified, Some(*provider), &[])
+    } else {
+      virtual_dir(name, child_url, modified)
+    });
+  }
+  entries.sort_by(|a, b| a.filename.cmp(&b.filename));
+  virtual_entry(filename, url, modified, None, Box::leak(entries.into_boxed_slice()))
+}
+
+fn virtual_entry(
+  filename: &'static str,
+  url: &'static str,
+  modified: u64,
+  provider: Option<Provider>,
+  entries: &'static [Entry],
+) -> Entry {
+  let is_dir = provider.is_none();
+  Entry {
+    filename: Cow::Borrowed(filename),
+    url: Cow::Borrowed(url),
+    size: 0,
+    mode: if is_dir { 0o555 } else { 0o444 },
+    nlink: if is_dir { 2 } else { 1 },
+    owner: "root",
+    group: "root",
+    modified,
+    created: modified,
+    is_dir,
+    link: None,
+    sha256: None,
+    meta: None,
+    provider,
+    content: None,
+    entries,
+  }
+}
+
+fn version() -> String {
+  let built = DateTime::<Utc>::from_timestamp(filesystem::ROOT.modified as i64, 0).unwrap();
+  format!(
+    "{} version {} ({}) {}",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_VERSION"),
+    env!("CARGO_PKG_REPOSITORY"),
+    built.format("%a %b %e %H:%M:%S UTC %Y")
+  )
+}
+
+fn uptime() -> String {
+  let uptime = (Utc::now() - *BOOT_TIME).num_milliseconds() as f64 / 1000.0;
+  format!("{:.2} {:.2}", uptime, uptime)
+}
+
+fn history() -> String {
+  let history = CMD_HISTORY.lock().unwrap();
+  history
+    .iter()
+    .enumerate()
+    .map(|(index, cmd)| format!("{:-4} {}\n", index, cmd))
+    .collect()
+}
+
+fn null() -> String {
+  String::new()
+}
+
+fn zero() -> String {
+  "\0".repeat(DEVICE_READ_SIZE)
+}
+
+fn random() -> String {
+  // xorshift seeded with the current time, good enough for noise
+  let mut seed = Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64 | 1;
+  (0..DEVICE_READ_SIZE)
+    .map(|_| {
+      seed ^= seed << 13;
+      seed ^= seed >> 7;
+      seed ^= seed << 17;
+      char::from(seed as u8)
+    })
+    .collect()
+}
@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use ansi_term::{Colour, Style};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::app::App;
+use crate::filesystem;
+use crate::less::Less;
+use crate::shell::Shell;
+use crate::termstate::TermState;
+use crate::utils::{Fetched, Validators};
+use crate::{consts, init, new, pending, prefix, utils, wrap, write, write_buf, write_error, write_stream, writeln_buf};
+
+mod tokenize;
+
+pub struct Index {
+  docs: &'static [Doc],
+  // sorted by term
+  terms: &'static [Term],
+}
+
+pub struct Doc {
+  url: &'static str,
+  // number of indexed terms
+  terms: u32,
+  // position of the first term of every line
+  lines: &'static [u32],
+}
+
+pub struct Term {
+  term: &'static str,
+  postings: &'static [Posting],
+}
+
+pub struct Posting {
+  doc: u32,
+  positions: &'static [u32],
+}
+
+// generated by build.rs, empty for chunked builds, which fetch the index instead
+include!(concat!(env!("OUT_DIR"), "/search.rs"));
+
+// index of chunked builds, next to the manifest of the root
+const INDEX_NAME: &str = ".search.ron";
+
+lazy_static! {
+  // fetched index, kept for the whole session once it loaded
+  static ref LOADED: Mutex<Option<&'static Index>> = Mutex::new(None);
+}
+
+// the index, None until a chunked build fetched it
+fn index() -> Option<&'static Index> {
+  if !filesystem::MANIFESTS {
+    return Some(&INDEX);
+  }
+  *LOADED.lock().unwrap()
+}
+
+#[derive(Deserialize)]
+struct FetchedIndex {
+  docs: Vec<FetchedDoc>,
+  terms: Vec<FetchedTerm>,
+}
+
+#[derive(Deserialize)]
+struct FetchedDoc {
+  url: String,
+  terms: u32,
+  lines: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct FetchedTerm {
+  term: String,
+  postings: Vec<FetchedPosting>,
+}
+
+#[derive(Deserialize)]
+struct FetchedPosting {
+  doc: u32,
+  positions: Vec<u32>,
+}
+
+// fetches the index of a chunked build, failures aren't kept, so the next search tries again
+async fn load() -> Result<&'static Index, String> {
+  let url = format!("{}{}", filesystem::ROOT_URL, INDEX_NAME);
+  let fetched: FetchedIndex = match utils::afetch(&url, &Validators::default()).await? {
+    Fetched::Content(content, _validators) => ron::from_str(&content).map_err(|error| error.to_string())?,
+    Fetched::NotModified => return Err("unexpected 304".to_string()),
+  };
+  let docs = fetched
+    .docs
+    .into_iter()
+    .map(|doc| Doc {
+      url: leak(doc.url),
+      terms: doc.terms,
+      lines: Box::leak(doc.lines.into_boxed_slice()),
+    })
+    .collect::<Vec<_>>();
+  let terms = fetched
+    .terms
+    .into_iter()
+    .map(|term| Term {
+      term: leak(term.term),
+      postings: Box::leak(
+        term
+          .postings
+          .into_iter()
+          .map(|posting| Posting {
+            doc: posting.doc,
+            positions: Box::leak(posting.positions.into_boxed_slice()),
+          })
+          .collect(),
+      ),
+    })
+    .collect::<Vec<_>>();
+  let index: &'static Index = Box::leak(Box::new(Index {
+    docs: Box::leak(docs.into_boxed_slice()),
+    terms: Box::leak(terms.into_boxed_slice()),
+  }));
+  *LOADED.lock().unwrap() = Some(index);
+  Ok(index)
+}
+
+// the index stays for the whole session, like the static one
+fn leak(text: String) -> &'static str {
+  Box::leak(text.into_boxed_str())
+}
+
+// BM25 parameters, term frequency saturation and document length normalization
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+const MAX_RESULTS: usize = 20;
+// characters of context shown before the first match of a snippet
+const SNIPPET_CONTEXT: usize = 20;
+
+struct Hit {
+  url: &'static str,
+  score: f64,
+  // line with the most query terms
+  line: usize,
+  // index terms that matched, typos resolve to the term they were taken for
+  terms: HashSet<&'static str>,
+}
+
+// score of a document, the terms it matched and (line, query term) of every match
+type Matches = (f64, HashSet<&'static str>, Vec<(usize, usize)>);
+
+// documents ranked by BM25, query terms missing from the index match close terms instead
+fn search(index: &'static Index, query: &str) -> Vec<Hit> {
+  if index.docs.is_empty() {
+    return vec![];
+  }
+  let total = index.docs.len() as f64;
+  let average = index.docs.iter().map(|doc| doc.terms as f64).sum::<f64>() / total;
+  let mut matches: HashMap<u32, Matches> = HashMap::new();
+  let mut query_terms = tokenize::terms(query);
+  // every term counts once, wherever it repeats in the query
+  query_terms.sort();
+  query_terms.dedup();
+  for (query_index, query_term) in query_terms.iter().enumerate() {
+    for (term, weight) in lookup(index, query_term) {
+      let idf = (1.0 + (total - term.postings.len() as f64 + 0.5) / (term.postings.len() as f64 + 0.5)).ln();
+      for posting in term.postings {
+        let doc = &index.docs[posting.doc as usize];
+        let frequency = posting.positions.len() as f64;
+        let norm = K1 * (1.0 - B + B * doc.terms as f64 / average);
+        let (score, terms, lines) = matches.entry(posting.doc).or_default();
+        *score += weight * idf * frequency * (K1 + 1.0) / (frequency + norm);
+        terms.insert(term.term);
+        lines.extend(posting.positions.iter().map(|position| {
+          (doc.lines.partition_point(|start| start <= position) - 1, query_index)
+        }));
+      }
+    }
+  }
+  let mut hits: Vec<Hit> = matches
+    .into_iter()
+    .map(|(doc, (score, terms, lines))| Hit {
+      url: index.docs[doc as usize].url,
+      score,
+      line: best_line(lines),
+      terms,
+    })
+    .collect();
+  hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.url.cmp(b.url)));
+  hits.truncate(MAX_RESULTS);
+  hits
+}
+
+// (term, weight) the query term matches, the exact one or the ones within the typo budget
+fn lookup(index: &'static Index, query_term: &str) -> Vec<(&'static Term, f64)> {
+  if let Ok(found) = index.terms.binary_search_by(|term| term.term.cmp(query_term)) {
+    return vec![(&index.terms[found], 1.0)];
+  }
+  let budget = match query_term.chars().count() {
+    0..=3 => return vec![],
+    4..=7 => 1,
+    _ => 2,
+  };
+  index
+    .terms
+    .iter()
+    .filter_map(|term| {
+      let distance = edit_distance(query_term, term.term, budget)?;
+      Some((term, 1.0 / (1.0 + distance as f64)))
+    })
+    .collect()
+}
+
+// levenshtein distance, None once it exceeds `budget`
+fn edit_distance(a: &str, b: &str, budget: usize) -> Option<usize> {
+  let a: Vec<char> = a.chars().collect();
+  let b: Vec<char> = b.chars().collect();
+  if a.len().abs_diff(b.len()) > budget {
+    return None;
+  }
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.iter().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = previous[j] + usize::from(ca != cb);
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    if current.iter().min().is_some_and(|min| *min > budget) {
+      return None;
+    }
+    previous = current;
+  }
+  previous.last().copied().filter(|distance| *distance <= budget)
+}
+
+// line with the most distinct query terms, the first one on ties
+fn best_line(mut lines: Vec<(usize, usize)>) -> usize {
+  lines.sort();
+  lines.dedup();
+  let mut best = (0, 0);
+  let mut start = 0;
+  while start < lines.len() {
+    let line = lines[start].0;
+    let count = lines[start..].iter().take_while(|(other, _query_term)| *other == line).count();
+    if count > best.1 {
+      best = (line, count);
+    }
+    start += count;
+  }
+  best.0
+}
+
+// the matching line of every hit, empty if its file can't be loaded
+async fn matched_lines(hits: Vec<(&'static str, usize)>) -> Vec<String> {
+  filesystem::prefetch(hits.iter().map(|(url, _line)| url.to_string()).collect()).await;
+  let mut lines = vec![];
+  for (url, line) in hits {
+    let content = match filesystem::get_file(url) {
+      Ok(file) => file.load().await.unwrap_or_default(),
+      Err(_) => String::new(),
+    };
+    lines.push(content.lines().nth(line).unwrap_or_default().to_string());
+  }
+  lines
+}
+
+// plain text of a line around its first match, with the matched words highlighted
+fn snippet(line: &str, terms: &HashSet<&'static str>, width: usize) -> String {
+  let plain = wrap::strip_ansi(line);
+  let plain = plain.trim();
+  let matched = |word: &str| tokenize::term(word).is_some_and(|term| terms.contains(term.as_str()));
+  let first = tokenize::words(plain)
+    .find(|word| matched(word))
+    .map(|word| plain[..word.as_ptr() as usize - plain.as_ptr() as usize].chars().count())
+    .unwrap_or(0);
+  let skip = first.saturating_sub(SNIPPET_CONTEXT);
+  let mut visible: String = plain.chars().skip(skip).take(width).collect();
+  if skip > 0 {
+    visible = format!("…{}", visible.chars().skip(1).collect::<String>());
+  }
+  let mut highlighted = String::new();
+  let mut rest = visible.as_str();
+  for word in tokenize::words(&visible) {
+    let start = word.as_ptr() as usize - rest.as_ptr() as usize;
+    highlighted.push_str(&rest[..start]);
+    if matched(word) {
+      highlighted.push_str(&Colour::Yellow.bold().paint(word).to_string());
+    } else {
+      highlighted.push_str(word);
+    }
+    rest = &rest[start + word.len()..];
+  }
+  highlighted.push_str(rest);
+  highlighted
+}
+
+// results of a search, enter opens the selected one at its matching line
+pub struct Search {
+  query: String,
+  hits: Vec<Hit>,
+  snippets: Vec<String>,
+  selected: usize,
+  top: usize,
+  ansi_buffer: Vec<char>,
+}
+
+impl App for Search {
+  fn readchar(&mut self, state: &mut TermState, input: char) -> Option<Box<dyn App>> {
+    if !self.ansi_buffer.is_empty() || input == '\x1b' {
+      self.ansi_buffer.push(input);
+      if input.is_ascii_alphabetic() || input == '~' {
+        let ansistr: String = self.ansi_buffer.drain(..).collect();
+        match ansistr.as_str() {
+          consts::UP => self.select(state, self.selected.saturating_sub(1)),
+          consts::DOWN => self.select(state, self.selected + 1),
+          _ => {}
+        }
+      }
+      return None;
+    }
+    match input {
+      'k' => {
+        self.select(state, self.selected.saturating_sub(1));
+        None
+      }
+      'j' => {
+        self.select(state, self.selected + 1);
+        None
+      }
+      '\r' | '\n' => {
+        let hit = &self.hits[self.selected];
+        let app = Less::open_at(state, &format!("/{}", hit.url), hit.line, true, |state, error| {
+          Shell::clear(state);
+          write_error!(state, format!("search: {}", error));
+        });
+        app.or_else(|| Some(Box::new(Shell::new())))
+      }
+      'q' => {
+        Shell::clear(state);
+        Some(Box::new(Shell::new()))
+      }
+      _ => None,
+    }
+  }
+
+  fn scroll(&mut self, state: &mut TermState, lines: i32) {
+    if lines > 0 {
+      self.select(state, self.selected.saturating_sub(1));
+    } else {
+      self.select(state, self.selected + 1);
+    }
+  }
+
+  fn autocomplete(&self, _state: &TermState) -> Vec<String> {
+    vec!['q'.to_string()]
+  }
+}
+
+impl Search {
+  // shows the results, chunked builds fetch the index first
+  pub fn open(state: &mut TermState, query: String) -> Option<Box<dyn App>> {
+    if let Some(index) = index() {
+      return Self::show(state, index, query);
+    }
+    pending::spawn(state, load(), move |state, index| match index {
+      Ok(index) => Self::show(state, index, query),
+      Err(error) => {
+        write_error!(state, format!("search: {}", error));
+        None
+      }
+    })
+  }
+
+  fn show(state: &mut TermState, index: &'static Index, query: String) -> Option<Box<dyn App>> {
+    let hits = search(index, &query);
+    if hits.is_empty() {
+      write_error!(state, format!("search: no results for {}", query));
+      return None;
+    }
+    // the index doesn't keep the text, the snippets come from the files
+    let lines = matched_lines(hits.iter().map(|hit| (hit.url, hit.line)).collect());
+    pending::spawn(state, lines, move |state, lines| {
+      let width = state.width.saturating_sub(4);
+      let snippets = hits
+        .iter()
+        .zip(lines)
+        .map(|(hit, line)| snippet(&line, &hit.terms, width))
+        .collect();
+      let mut search = Search {
+        query,
+        hits,
+        snippets,
+        selected: 0,
+        top: 0,
+        ansi_buffer: vec![],
+      };
+      search.render(state);
+      let app_box: Box<dyn App> = Box::new(search);
+      Some(app_box)
+    })
+  }
+
+  fn select(&mut self, state: &mut TermState, selected: usize) {
+    self.selected = selected.min(self.hits.len() - 1);
+    self.render(state);
+  }
+
+  fn render(&mut self, state: &mut TermState) {
+    // header and footer take a line each, every hit two
+    let visible = (state.height.saturating_sub(2) / 2).max(1);
+    if self.selected < self.top {
+      self.top = self.selected;
+    } else if self.selected >= self.top + visible {
+      self.top = self.selected + 1 - visible;
+    }
+    let mut lines = vec![format!("{} results for \"{}\"", self.hits.len(), self.query)];
+    for (index, (hit, snippet)) in self.hits.iter().zip(&self.snippets).enumerate().skip(self.top).take(visible) {
+      let location = format!("/{}:{}", hit.url, hit.line + 1);
+      lines.push(if index == self.selected {
+        format!("> {}", Colour::Blue.bold().reverse().paint(location))
+      } else {
+        format!("  {}", Colour::Blue.bold().paint(location))
+      });
+      lines.push(format!("    {}", snippet));
+    }
+    let padding = state.height.saturating_sub(lines.len());
+    let footer = Style::new()
+      .on(Colour::RGB(234, 255, 229))
+      .fg(Colour::Black)
+      .paint("j/k: move, enter: open, q: quit")
+      .to_string();
+    write!(
+      "{}{}{}{}",
+      consts::NEWLINE.repeat(padding),
+      lines.join(consts::NEWLINE),
+      consts::NEWLINE,
+      footer
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a: "rust exploit" / "rust wasm", b: "wasm shell wasm shell" / "rust shell terminal kernel"
+  static INDEX: Index = Index {
+    docs: &[
+      Doc { url: "posts/a.md", terms: 4, lines: &[0, 2] },
+      Doc { url: "posts/b.md", terms: 8, lines: &[0, 4] },
+    ],
+    terms: &[
+      Term { term: "exploit", postings: &[Posting { doc: 0, positions: &[1] }] },
+      Term { term: "kernel", postings: &[Posting { doc: 1, positions: &[7] }] },
+      Term {
+        term: "rust",
+        postings: &[Posting { doc: 0, positions: &[0, 2] }, Posting { doc: 1, positions: &[4] }],
+      },
+      Term { term: "shell", postings: &[Posting { doc: 1, positions: &[1, 3, 5] }] },
+      Term { term: "terminal", postings: &[Posting { doc: 1, positions: &[6] }] },
+      Term {
+        term: "wasm",
+        postings: &[Posting { doc: 0, positions: &[3] }, Posting { doc: 1, positions: &[0, 2] }],
+      },
+    ],
+  };
+
+  fn urls(query: &str) -> Vec<&'static str> {
+    search(&INDEX, query).iter().map(|hit| hit.url).collect()
+  }
+
+  #[test]
+  fn ranks_by_bm25() {
+    // same frequency in a shorter document
+    assert_eq!(urls("rust"), ["posts/a.md", "posts/b.md"]);
+    // twice as frequent in a longer document
+    assert_eq!(urls("wasm"), ["posts/b.md", "posts/a.md"]);
+    assert_eq!(urls("terminal"), ["posts/b.md"]);
+    assert!(urls("missing").is_empty());
+  }
+
+  #[test]
+  fn repeated_query_terms_count_once() {
+    let scores = |query| search(&INDEX, query).iter().map(|hit| hit.score).collect::<Vec<_>>();
+    assert_eq!(scores("rust rust"), scores("rust"));
+  }
+
+  #[test]
+  fn hits_point_at_the_best_line() {
+    let hits = search(&INDEX, "rust shell");
+    assert_eq!(hits[0].url, "posts/b.md");
+    assert_eq!(hits[0].line, 1);
+    assert_eq!(hits[1].line, 0);
+  }
+
+  #[test]
+  fn typos_within_the_budget_match() {
+    assert_eq!(urls("kernal"), ["posts/b.md"]);
+    let hits = search(&INDEX, "kernal");
+    assert_eq!(hits[0].terms, HashSet::from(["kernel"]));
+    // a swap is two edits, short terms get none
+    assert!(urls("rsut").is_empty());
+    assert!(urls("rus").is_empty());
+  }
+
+  #[test]
+  fn edit_distance_stops_at_the_budget() {
+    assert_eq!(edit_distance("rust", "rust", 0), Some(0));
+    assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+    assert_eq!(edit_distance("kitten", "sitting", 2), None);
+    assert_eq!(edit_distance("wasm", "terminal", 2), None);
+  }
+
+  #[test]
+  fn best_line_counts_distinct_query_terms() {
+    assert_eq!(best_line(vec![(3, 0), (1, 0), (1, 1), (3, 0)]), 1);
+    assert_eq!(best_line(vec![(0, 0), (0, 0), (0, 0), (4, 0), (4, 1)]), 4);
+    // the first one on ties
+    assert_eq!(best_line(vec![(5, 1), (2, 0)]), 2);
+  }
+
+  #[test]
+  fn snippets_highlight_matches() {
+    let terms = HashSet::from(["rust"]);
+    let rust = Colour::Yellow.bold().paint("Rust").to_string();
+    assert_eq!(snippet("  I like \x1b[1mRust\x1b[0m a lot ", &terms, 80), format!("I like {} a lot", rust));
+    assert_eq!(snippet("I like Rust a lot", &terms, 11), format!("I like {}", rust));
+    // a cut off word doesn't match anymore
+    assert_eq!(snippet("I like Rust a lot", &terms, 10), "I like Rus");
+    assert_eq!(snippet("no match here", &terms, 80), "no match here");
+  }
+
+  #[test]
+  fn snippets_start_near_the_first_match() {
+    let terms = HashSet::from(["rust"]);
+    let line = format!("{}rust", "x ".repeat(30));
+    let rust = Colour::Yellow.bold().paint("rust").to_string();
+    assert_eq!(snippet(&line, &terms, 80), format!("…{}{}", " x".repeat(9) + " ", rust));
+  }
+}
@@ -0,0 +1,119 @@
+// terms of the search index, build.rs includes this file so the index is built with the same terms
+// the search command looks up
+
+use crate::wrap;
+
+// words too common to tell documents apart, sorted for binary search
+const STOPWORDS: &[&str] = &[
+  "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "because", "been",
+  "but", "by", "can", "could", "did", "do", "does", "for", "from", "had", "has", "have", "he", "her",
+  "his", "how", "i", "if", "in", "into", "is", "it", "its", "just", "me", "more", "my", "no", "not",
+  "of", "on", "one", "only", "or", "other", "our", "out", "over", "she", "so", "some", "than", "that",
+  "the", "their", "them", "then", "there", "these", "they", "this", "to", "up", "us", "was", "we",
+  "were", "what", "when", "which", "who", "will", "with", "would", "you", "your",
+];
+
+// longer tokens are hashes, addresses or base64 and never searched for
+const MAX_TERM_LEN: usize = 32;
+
+// runs of letters and digits of plain text
+pub fn words(plain: &str) -> impl Iterator<Item = &str> {
+  plain
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+}
+
+// indexed form of a word, None for words that aren't indexed
+pub fn term(word: &str) -> Option<String> {
+  let word = word.to_lowercase();
+  if word.chars().count() < 2 || word.len() > MAX_TERM_LEN || STOPWORDS.binary_search(&word.as_str()).is_ok() {
+    return None;
+  }
+  Some(stem(&word))
+}
+
+// terms of a line, escape sequences are skipped
+pub fn terms(line: &str) -> Vec<String> {
+  words(&wrap::strip_ansi(line)).filter_map(term).collect()
+}
+
+// light suffix stripping, "exploits", "exploited" and "exploiting" share the term "exploit"
+pub fn stem(word: &str) -> String {
+  let mut stem = word.to_string();
+  for (suffix, replacement) in [("sses", "ss"), ("ies", "y"), ("ss", "ss"), ("s", "")] {
+    if let Some(base) = stem.strip_suffix(suffix) {
+      if base.len() >= 2 {
+        stem = format!("{}{}", base, replacement);
+      }
+      break;
+    }
+  }
+  const SUFFIXES: &[(&str, &str)] = &[
+    ("ational", "ate"),
+    ("ization", "ize"),
+    ("fulness", "ful"),
+    ("iveness", "ive"),
+    ("ement", ""),
+    ("ment", ""),
+    ("ness", ""),
+    ("ingly", ""),
+    ("edly", ""),
+    ("ing", ""),
+    ("ed", ""),
+    ("ly", ""),
+  ];
+  for (suffix, replacement) in SUFFIXES {
+    let Some(base) = stem.strip_suffix(suffix) else {
+      continue;
+    };
+    // the rest has to look like a word on its own
+    if base.len() < 3 || !base.chars().any(|c| "aeiouy".contains(c)) {
+      break;
+    }
+    let mut base = format!("{}{}", base, replacement);
+    // running -> run, but not install -> instal
+    let mut chars = base.chars().rev();
+    if let (Some(last), Some(before)) = (chars.next(), chars.next()) {
+      if replacement.is_empty() && last == before && !"lsz".contains(last) && !"aeiou".contains(last) {
+        base.pop();
+      }
+    }
+    stem = base;
+    break;
+  }
+  stem
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stopwords_are_sorted() {
+    assert!(STOPWORDS.windows(2).all(|pair| pair[0] < pair[1]));
+  }
+
+  #[test]
+  fn short_common_and_long_words_are_skipped() {
+    assert_eq!(term("a"), None);
+    assert_eq!(term("The"), None);
+    assert_eq!(term(&"f".repeat(MAX_TERM_LEN + 1)), None);
+    assert_eq!(term("Rust"), Some("rust".to_string()));
+  }
+
+  #[test]
+  fn suffixes_share_a_stem() {
+    for word in ["exploit", "exploits", "exploited", "exploiting"] {
+      assert_eq!(stem(word), "exploit");
+    }
+    assert_eq!(stem("running"), "run");
+    assert_eq!(stem("installing"), "install");
+    assert_eq!(stem("classes"), "class");
+    assert_eq!(stem("stories"), "story");
+  }
+
+  #[test]
+  fn terms_skip_escapes() {
+    assert_eq!(terms("\x1b[1mRust\x1b[0m exploits, the-kernel"), ["rust", "exploit", "kernel"]);
+  }
+}
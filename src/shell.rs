@@ -6,8 +6,10 @@ use crate::app::App;
 use crate::cmds::{self, CMD_HISTORY, COMMANDS, CmdType};
 use crate::termstate::TermState;
 use crate::utils::{longest_common_prefix};
+use crate::utils::Stream;
 use crate::{
-  consts, filesystem, init, new, prefix, utils, write, write_buf, write_solo, writeln_buf,
+  consts, filesystem, init, new, overlay, pending, prefix, utils, write, write_buf, write_error, write_solo,
+  write_stream, writeln_buf,
 };
 
 pub struct Shell {
@@ -194,40 +196,45 @@ impl Shell {
       None => return vec![],
     };
     let search = &inputstr[wsi + 1..];
+    if search == "~" {
+      return vec!["~/".to_string()];
+    }
 
     let mut search_vals = search.rsplitn(2, '/');
     let filename = search_vals.next().unwrap();
     let crnt_path = search_vals.next().unwrap_or("");
 
     let path = if search.starts_with('/') {
-      crnt_path
+      crnt_path.to_string()
     } else if search.contains('/') {
-      &state.path.join(crnt_path)
+      state.path.join(crnt_path)
     } else {
-      state.path.url
+      state.path.url.to_string()
     };
 
     let resolved = utils::resolve_path(path);
-    let change = filesystem::ROOT.get_file(&resolved);
+    filesystem::prefetch_later(resolved.clone());
+    let change = filesystem::get_file(&resolved);
     if change.is_err() || !change.clone().unwrap().is_dir {
       return vec![];
     }
 
     let dir = if resolved.is_empty() {
-      &filesystem::ROOT
+      filesystem::root()
     } else {
       change.unwrap()
     };
-    let mut entries: Vec<_> = dir.entries.keys().cloned().collect();
+    let mut entries: Vec<_> = dir.children().into_iter().map(|(name, _entry)| name).collect();
     if dir.url != "" {
-      entries.append(&mut vec![".."]);
+      entries.append(&mut vec!["..".to_string()]);
     }
 
     let filtered_entries: Vec<_> = entries
       .iter()
       .filter(|entry| entry.starts_with(&filename))
       .map(|entry| {
-        if dir.get_file(entry.to_string()).unwrap().is_dir {
+        // broken links complete like files
+        if filesystem::get_file(dir.join(entry.as_str())).is_ok_and(|file| file.is_dir) {
           format!("{}/", entry)
         } else {
           entry.to_string()
@@ -316,26 +323,98 @@ impl Shell {
     }
     self.history_index = history.len();
     drop(history);
+    let (cmdline, redirections) = match parse_redirections(cmdline) {
+      Ok(parsed) => parsed,
+      Err(error) => {
+        write_error!(state, format!("sh: {}", error));
+        return None;
+      }
+    };
+    // directories the command line touches, chunked builds fetch their listings first
+    let paths: Vec<String> = [state.path.url.to_string()]
+      .into_iter()
+      .chain(
+        cmdline
+          .split(' ')
+          .skip(1)
+          .chain(redirections.targets())
+          .filter(|arg| !arg.is_empty() && !arg.starts_with('-'))
+          .map(|arg| utils::resolve_path(state.path.join(arg))),
+      )
+      .collect();
+    pending::spawn(state, filesystem::prefetch(paths), move |state, ()| {
+      Shell::load_sources(state, cmdline, redirections)
+    })
+  }
+
+  fn load_sources(state: &mut TermState, cmdline: String, redirections: Redirections) -> Option<Box<dyn App>> {
+    let checked = redirections
+      .sources(state)
+      .and_then(|sources| Ok((sources, redirections.appended(state)?)));
+    let (sources, appended) = match checked {
+      Ok(checked) => checked,
+      Err(error) => {
+        write_error!(state, format!("sh: {}", error));
+        return None;
+      }
+    };
+    pending::spawn(
+      state,
+      async move {
+        let mut loaded = vec![];
+        for file in sources {
+          let content = file.load().await?;
+          loaded.push((file, content));
+        }
+        // static files that are appended to are copied up before the command runs
+        for (target, resolved) in appended {
+          overlay::append(&resolved, "").await.map_err(|error| format!("{}: {}", target, error))?;
+        }
+        Ok::<_, String>(loaded)
+      },
+      move |state, loaded| match loaded {
+        Ok(loaded) => Shell::execute(state, &cmdline, redirections, loaded),
+        Err(error) => {
+          write_error!(state, format!("sh: {}", error));
+          None
+        }
+      },
+    )
+  }
+
+  fn execute(
+    state: &mut TermState,
+    cmdline: &str,
+    redirections: Redirections,
+    loaded: Vec<(filesystem::Node, String)>,
+  ) -> Option<Box<dyn App>> {
+    if let Err(error) = redirections.open(state, loaded) {
+      write_error!(state, format!("sh: {}", error));
+      return None;
+    }
     let mut cmd_args = cmdline.split(" ");
-    let cmd = cmd_args.next()?;
+    let cmd = cmd_args.next().unwrap_or("");
 
     let cmd_info = {
       let commands = COMMANDS.lock().unwrap();
       commands.get(cmd).cloned()
     };
 
-    return if let Some(cmd_info) = cmd_info {
+    let app = if let Some(cmd_info) = cmd_info {
       (cmd_info.func)(state, cmdline.trim_end_matches(' '))
     } else if !cmd.is_empty() {
       state.cursor_y += 2;
       state.cursor_x = consts::PREFIX.len();
-      write_solo!(state, format!("command not found: {}, try using help", cmd));
+      write_error!(state, format!("command not found: {}, try using help", cmd));
       None
     } else {
       state.cursor_y += 1;
       init!(state);
       None
     };
+    // output of pending commands is only complete once they are done
+    pending::defer(state, move |state| redirections.close(state));
+    app
   }
 
   fn ansi_clear(&mut self) {
@@ -412,3 +491,144 @@ impl Shell {
     }
   }
 }
+
+// files a command line reads from or writes to, targets are relative to the working directory
+#[derive(Default)]
+struct Redirections {
+  stdin: Option<String>,
+  // target and whether output is appended
+  stdout: Option<(String, bool)>,
+  stderr: Option<(String, bool)>,
+}
+
+impl Redirections {
+  fn targets(&self) -> impl Iterator<Item = &str> {
+    let outputs = [&self.stdout, &self.stderr].into_iter().flatten().map(|(target, _append)| target);
+    self.stdin.iter().chain(outputs).map(|target| target.as_str())
+  }
+
+  // static files the redirections read, they are loaded before the command runs
+  fn sources(&self, state: &TermState) -> Result<Vec<filesystem::Node>, String> {
+    let mut sources = vec![];
+    if let Some(target) = &self.stdin {
+      let file = filesystem::get_file(utils::resolve_path(state.path.join(target.clone())))
+        .map_err(|_| format!("{}: No such file or directory", target))?;
+      if file.is_dir {
+        return Err(format!("{}: Is a directory", target));
+      }
+      sources.push(file);
+    }
+    Ok(sources)
+  }
+
+  // (target, resolved path) of the outputs that are appended to
+  // output only goes to session files under /tmp, site files can't be shadowed by it
+  fn appended(&self, state: &TermState) -> Result<Vec<(String, String)>, String> {
+    let mut appended = vec![];
+    for (target, append) in [&self.stdout, &self.stderr].into_iter().flatten() {
+      let resolved = utils::resolve_path(state.path.join(target.clone()));
+      if !overlay::is_tmp(&resolved) {
+        return Err(format!("{}: Permission denied", target));
+      }
+      if *append {
+        appended.push((target.clone(), resolved));
+      }
+    }
+    Ok(appended)
+  }
+
+  // feeds stdin and truncates the output files before the command runs
+  fn open(&self, state: &mut TermState, loaded: Vec<(filesystem::Node, String)>) -> Result<(), String> {
+    state.stdin = loaded.into_iter().next().map(|(_file, content)| content);
+    for (target, append) in [&self.stdout, &self.stderr].into_iter().flatten() {
+      let resolved = utils::resolve_path(state.path.join(target.clone()));
+      overlay::write(&resolved, "", *append).map_err(|error| format!("{}: {}", target, error))?;
+    }
+    for (stream, target) in [(Stream::Stdout, &self.stdout), (Stream::Stderr, &self.stderr)] {
+      if target.is_some() {
+        utils::capture(stream);
+      }
+    }
+    Ok(())
+  }
+
+  fn close(&self, state: &mut TermState) {
+    state.stdin = None;
+    for (stream, target) in [(Stream::Stdout, &self.stdout), (Stream::Stderr, &self.stderr)] {
+      // open truncated the targets of >, so the output is appended for > and >> alike
+      if let (Some(captured), Some((target, _append))) = (utils::take_capture(stream), target) {
+        let resolved = utils::resolve_path(state.path.join(target.clone()));
+        let target = target.clone();
+        // the command may have replaced the file with a static copy, which is loaded first then
+        wasm_bindgen_futures::spawn_local(async move {
+          if let Err(error) = overlay::append(&resolved, &captured).await {
+            warn!("failed to write {}: {}", target, error);
+          }
+        });
+      }
+    }
+  }
+}
+
+fn parse_redirections(cmdline: &str) -> Result<(String, Redirections), String> {
+  let mut redirections = Redirections::default();
+  let mut args: Vec<&str> = vec![];
+  let mut tokens = cmdline.split(' ');
+  while let Some(token) = tokens.next() {
+    let Some(op) = ["2>>", "2>", ">>", ">", "<"].into_iter().find(|op| token.starts_with(op)) else {
+      args.push(token);
+      continue;
+    };
+    // the target may be attached to the operator or follow it
+    let rest = &token[op.len()..];
+    let target = if rest.is_empty() {
+      tokens.find(|token| !token.is_empty())
+    } else {
+      Some(rest)
+    }
+    .ok_or("syntax error near unexpected token `newline'")?
+    .to_string();
+    match op {
+      "<" => redirections.stdin = Some(target),
+      ">" | ">>" => redirections.stdout = Some((target, op == ">>")),
+      _ => redirections.stderr = Some((target, op == "2>>")),
+    }
+  }
+  Ok((args.join(" "), redirections))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn commands_without_redirections() {
+    let (cmdline, redirections) = parse_redirections("ls -l posts").unwrap();
+    assert_eq!(cmdline, "ls -l posts");
+    assert_eq!(redirections.targets().count(), 0);
+  }
+
+  #[test]
+  fn output_redirections() {
+    let (cmdline, redirections) = parse_redirections("echo hi > out 2>> errors").unwrap();
+    assert_eq!(cmdline, "echo hi");
+    assert_eq!(redirections.stdout, Some(("out".to_string(), false)));
+    assert_eq!(redirections.stderr, Some(("errors".to_string(), true)));
+    let (_cmdline, redirections) = parse_redirections("echo hi >>log 2>err").unwrap();
+    assert_eq!(redirections.stdout, Some(("log".to_string(), true)));
+    assert_eq!(redirections.stderr, Some(("err".to_string(), false)));
+  }
+
+  #[test]
+  fn input_redirection() {
+    let (cmdline, redirections) = parse_redirections("cat <  notes.md").unwrap();
+    assert_eq!(cmdline, "cat");
+    assert_eq!(redirections.stdin.as_deref(), Some("notes.md"));
+  }
+
+  #[test]
+  fn missing_target() {
+    assert!(parse_redirections("echo hi >").is_err());
+    assert!(parse_redirections("cat <").is_err());
+  }
+}
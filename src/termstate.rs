@@ -21,7 +21,11 @@ macro_rules! clearln {
 }
 
 pub struct TermState {
-  pub path: &'static filesystem::Entry,
+  pub path: filesystem::Node,
+  pub oldpwd: Option<filesystem::Node>,
+  pub dir_stack: Vec<filesystem::Node>,
+  // content redirected into the running command with <
+  pub stdin: Option<String>,
   pub cursor_x: usize,
   pub cursor_y: usize,
   pub height: usize,
@@ -31,7 +35,10 @@ pub struct TermState {
 impl TermState {
   pub fn new() -> Self {
     Self {
-      path: &filesystem::ROOT,
+      path: filesystem::root(),
+      oldpwd: None,
+      dir_stack: vec![],
+      stdin: None,
       cursor_x: 0,
       cursor_y: 0,
       height: 0,
@@ -0,0 +1,47 @@
+// markdown front matter, build.rs includes this file to read titles, dates and tags
+
+// (delimiter, front matter, body) of a file starting with a --- (YAML) or +++ (TOML) block
+pub fn front_matter(content: &str) -> Option<(&str, &str, &str)> {
+  let delimiter = ["---", "+++"]
+    .into_iter()
+    .find(|delimiter| content.lines().next().is_some_and(|line| line.trim_end() == *delimiter))?;
+  let start = content.find('\n')? + 1;
+  let mut offset = start;
+  for line in content[start..].split_inclusive('\n') {
+    if line.trim_end() == delimiter {
+      return Some((delimiter, &content[start..offset], &content[offset + line.len()..]));
+    }
+    offset += line.len();
+  }
+  None
+}
+
+// content without its front matter, the part that is shown and indexed
+pub fn body(content: &str) -> &str {
+  front_matter(content).map_or(content, |(_delimiter, _front_matter, body)| body)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn front_matter_yaml() {
+    let content = "---\ntitle: Hello\n---\nbody\n";
+    assert_eq!(front_matter(content), Some(("---", "title: Hello\n", "body\n")));
+    assert_eq!(body(content), "body\n");
+  }
+
+  #[test]
+  fn front_matter_toml() {
+    let content = "+++\ntitle = \"Hello\"\n+++\nbody\n";
+    assert_eq!(front_matter(content), Some(("+++", "title = \"Hello\"\n", "body\n")));
+  }
+
+  #[test]
+  fn front_matter_missing_or_unclosed() {
+    assert_eq!(front_matter("# title\n---\n"), None);
+    assert_eq!(front_matter("---\ntitle: Hello\n"), None);
+    assert_eq!(body("---\ntitle: Hello\n"), "---\ntitle: Hello\n");
+  }
+}
@@ -1,10 +1,12 @@
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
+use md5::Md5;
+use sha2::{Digest, Sha256};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{window, XmlHttpRequest};
+use web_sys::{window, Storage};
 use web_sys::{Request, RequestInit, RequestMode, Response};
 
 use crate::consts;
@@ -41,6 +43,15 @@ macro_rules! writeln {
     }};
 }
 
+#[macro_export]
+macro_rules! ewriteln {
+    ($state:expr, $($arg:tt)*) => {{
+        $state.cursor_y += 1;
+        let formatted = format!($($arg)*);
+        utils::writeln_stream(utils::Stream::Stderr, formatted);
+    }};
+}
+
 #[macro_export]
 macro_rules! writeln_buf {
     ($state:expr, $($arg:tt)*) => {{
@@ -50,8 +61,15 @@ macro_rules! writeln_buf {
     }};
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+  Stdout,
+  Stderr,
+}
+
 lazy_static! {
   static ref WRITE_BUFFER: Mutex<Vec<char>> = Mutex::new(vec![]);
+  static ref CAPTURES: Mutex<[Option<String>; 2]> = Mutex::new([None, None]);
 }
 
 pub fn write(out_str: impl Into<String>) {
@@ -67,8 +85,39 @@ pub fn write_buf(out_str: impl Into<String>) {
 }
 
 pub fn writeln(out_str: impl Into<String>) {
+  writeln_stream(Stream::Stdout, out_str);
+}
+
+pub fn writeln_stream(stream: Stream, out_str: impl Into<String>) {
   let out = out_str.into();
-  write(out + consts::NEWLINE);
+  if !write_captured(stream, &(out.clone() + consts::NEWLINE)) {
+    write(out + consts::NEWLINE);
+  }
+}
+
+// start collecting everything written to a stream instead of printing it
+pub fn capture(stream: Stream) {
+  CAPTURES.lock().unwrap()[stream as usize] = Some(String::new());
+}
+
+pub fn take_capture(stream: Stream) -> Option<String> {
+  CAPTURES.lock().unwrap()[stream as usize].take()
+}
+
+// output of a captured stream goes into a file, so it stays without colors
+pub fn is_captured(stream: Stream) -> bool {
+  CAPTURES.lock().unwrap()[stream as usize].is_some()
+}
+
+// returns false if the stream isn't captured and the output still has to be printed
+pub fn write_captured(stream: Stream, out: &str) -> bool {
+  match CAPTURES.lock().unwrap()[stream as usize].as_mut() {
+    Some(captured) => {
+      captured.push_str(&out.replace(consts::NEWLINE, "\n"));
+      true
+    }
+    None => false,
+  }
 }
 
 #[wasm_bindgen]
@@ -101,6 +150,33 @@ pub fn change_url(new_url_str: impl Into<String>) -> Result<(), JsValue> {
     .map_err(|err| err.into())
 }
 
+#[cfg(not(test))]
+fn local_storage() -> Option<Storage> {
+  window()?.local_storage().ok()?
+}
+
+// unit tests run on the host without a window, the overlay starts empty there and isn't persisted
+#[cfg(test)]
+fn local_storage() -> Option<Storage> {
+  None
+}
+
+pub fn storage_get(key: &str) -> Option<String> {
+  local_storage()?.get_item(key).ok()?
+}
+
+pub fn storage_set(key: &str, value: &str) {
+  if let Some(storage) = local_storage() {
+    let _ = storage.set_item(key, value);
+  }
+}
+
+pub fn storage_remove(key: &str) {
+  if let Some(storage) = local_storage() {
+    let _ = storage.remove_item(key);
+  }
+}
+
 pub fn resolve_path_files(path: &str) -> Vec<&str> {
   let components: Vec<&str> = path.split('/').collect();
   let mut resolved_components: Vec<&str> = Vec::new();
@@ -126,8 +202,19 @@ pub fn resolve_path_files(path: &str) -> Vec<&str> {
   resolved_components
 }
 
+// `~` and `$HOME` at the start of a path, None if it doesn't start with either
+pub fn expand_home(path: &str) -> Option<String> {
+  let rest = path.strip_prefix('~').or_else(|| path.strip_prefix("$HOME"))?;
+  if !rest.is_empty() && !rest.starts_with('/') {
+    return None;
+  }
+  Some(format!("{}{}", consts::HOME, rest))
+}
+
+// `~` only counts at the start, Entry::join leaves such paths as they are
 pub fn resolve_path(path_str: impl Into<String>) -> String {
   let path = path_str.into();
+  let path = expand_home(&path).unwrap_or(path);
   let mut out = resolve_path_files(&path).join("/");
   if out.starts_with("/") {
     out.remove(0);
@@ -135,53 +222,81 @@ pub fn resolve_path(path_str: impl Into<String>) -> String {
   out
 }
 
-#[deprecated]
-pub fn fetch(url_str: impl Into<String>) -> Result<String, String> {
-  let url = url_str.into();
-  // Create a new XMLHttpRequest to fetch the file
-  let xhr = XmlHttpRequest::new().expect("failed to create XmlRttpRequest");
-  xhr
-    .open_with_async(&"GET", &url, false)
-    .expect("failed to open");
-
-  // Send the request synchronously
-  xhr.send().expect("failed to send request");
-
-  // Check if the request was successful (status code 200)
-  if xhr.status().expect("failed to get status") != 200 {
-    return Err("HTTP request failed".to_string());
-  }
-
-  // Convert the response to a Vec<u8>
-  let response = xhr.response_text().unwrap().unwrap();
+// validators of a cached response, sent along to revalidate it
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+  pub etag: Option<String>,
+  pub last_modified: Option<String>,
+}
 
-  Ok(response)
+pub enum Fetched {
+  NotModified,
+  Content(String, Validators),
 }
 
-pub async fn afetch(url_str: impl Into<String>) -> Result<String, String> {
+pub async fn afetch(url_str: impl Into<String>, validators: &Validators) -> Result<Fetched, String> {
   let url = url_str.into();
   let opts = RequestInit::new();
   opts.set_method("GET");
   opts.set_mode(RequestMode::Cors);
 
-  let request = Request::new_with_str_and_init(&url, &opts).expect("failed to create request");
+  let request = Request::new_with_str_and_init(&url, &opts).map_err(js_error)?;
+  let headers = request.headers();
+  if let Some(etag) = &validators.etag {
+    headers.set("If-None-Match", etag).map_err(js_error)?;
+  }
+  if let Some(last_modified) = &validators.last_modified {
+    headers.set("If-Modified-Since", last_modified).map_err(js_error)?;
+  }
 
-  let window = window().unwrap();
+  let window = window().ok_or("no window to fetch from")?;
   let resp_value = JsFuture::from(window.fetch_with_request(&request))
     .await
-    .expect("request failed");
+    .map_err(js_error)?;
 
   // `resp_value` is a `Response` object.
-  assert!(resp_value.is_instance_of::<Response>());
-  let resp: Response = resp_value.dyn_into().unwrap();
+  let resp: Response = resp_value.dyn_into().map_err(js_error)?;
+  match resp.status() {
+    304 => return Ok(Fetched::NotModified),
+    status if !resp.ok() => return Err(format!("HTTP {} {}", status, resp.status_text())),
+    _ => {}
+  }
+  let validators = Validators {
+    etag: resp.headers().get("ETag").ok().flatten(),
+    last_modified: resp.headers().get("Last-Modified").ok().flatten(),
+  };
 
   // Convert this other `Promise` into a rust `Future`.
-  let text = JsFuture::from(resp.text().expect("failed to get text promise"))
+  let text = JsFuture::from(resp.text().map_err(js_error)?)
     .await
-    .expect("failed to get text from promise");
+    .map_err(js_error)?;
+
+  text
+    .as_string()
+    .map(|text| Fetched::Content(text, validators))
+    .ok_or("response is not text".to_string())
+}
+
+fn js_error(error: JsValue) -> String {
+  error.as_string().unwrap_or_else(|| format!("{:?}", error))
+}
 
-  // Send the JSON response back to JS.
-  Ok(text.as_string().expect("failed to get text"))
+// resolves after `ms` milliseconds
+pub async fn sleep(ms: i32) {
+  let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+    if let Some(window) = window() {
+      let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms);
+    }
+  });
+  let _ = JsFuture::from(promise).await;
+}
+
+pub fn sha256_hex(content: &str) -> String {
+  format!("{:x}", Sha256::digest(content))
+}
+
+pub fn md5_hex(content: &str) -> String {
+  format!("{:x}", Md5::digest(content))
 }
 
 pub fn human_size(size: u64) -> String {
@@ -229,3 +344,42 @@ pub fn longest_common_prefix(strs: Vec<&str>) -> String {
 
   prefix
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn home(rest: &str) -> String {
+    format!("{}{}", consts::HOME, rest)
+  }
+
+  #[test]
+  fn home_expands_only_at_the_start() {
+    assert_eq!(expand_home("~"), Some(home("")));
+    assert_eq!(expand_home("~/posts"), Some(home("/posts")));
+    assert_eq!(expand_home("$HOME/posts"), Some(home("/posts")));
+    assert_eq!(expand_home("posts/~"), None);
+    assert_eq!(expand_home("~user"), None);
+    assert_eq!(expand_home("$HOMEDIR"), None);
+  }
+
+  #[test]
+  fn resolve_expands_home() {
+    assert_eq!(resolve_path("~"), resolve_path(consts::HOME));
+    assert_eq!(resolve_path("~/a/../b"), resolve_path(home("/b")));
+    assert_eq!(resolve_path("$HOME/b"), resolve_path(home("/b")));
+  }
+
+  #[test]
+  fn tilde_components_are_names() {
+    assert_eq!(resolve_path("/posts/~"), "posts/~");
+    assert_eq!(resolve_path("/a/~/b"), "a/~/b");
+  }
+
+  #[test]
+  fn dots_resolve() {
+    assert_eq!(resolve_path("/posts/./a/../b.md"), "posts/b.md");
+    assert_eq!(resolve_path("/../.."), "");
+    assert_eq!(resolve_path_files("/posts//a.md"), ["posts", "a.md"]);
+  }
+}
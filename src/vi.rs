@@ -0,0 +1,652 @@
+use ansi_term::{Colour, Style};
+use log::info;
+
+use crate::app::App;
+use crate::filesystem::{self, Node};
+use crate::shell::Shell;
+use crate::termstate::TermState;
+use crate::{consts, init, new, overlay, pending, prefix, utils, wrap, write, write_buf, write_error, write_stream, writeln_buf};
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, PartialEq)]
+enum Mode {
+  Normal,
+  Insert,
+  // prompt character (`:` or `/`) and the typed line
+  Command(char, String),
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+  Whitespace,
+  Word,
+  Punctuation,
+  // empty lines count as a word of their own
+  Empty,
+}
+
+pub struct Vi {
+  name: String,
+  resolved: String,
+  readonly: bool,
+  modified: bool,
+  lines: Vec<Vec<char>>,
+  row: usize,
+  col: usize,
+  top: usize,
+  left: usize,
+  mode: Mode,
+  pending: Option<char>,
+  register: Vec<Vec<char>>,
+  search: Option<Vec<char>>,
+  message: String,
+  ansi_buffer: Vec<char>,
+  // mode and column before a lone escape, restored if it starts a sequence of the same input
+  escaped: Option<(Mode, usize)>,
+}
+
+impl App for Vi {
+  fn readchar(&mut self, state: &mut TermState, input: char) -> Option<Box<dyn App>> {
+    if let Some((mode, col)) = self.escaped.take() {
+      if input == '[' || input == 'O' {
+        self.mode = mode;
+        self.col = col;
+        self.ansi_buffer.push(input);
+        return None;
+      }
+      self.ansi_buffer.clear();
+    }
+    if !self.ansi_buffer.is_empty() {
+      self.ansi_buffer.push(input);
+      if input.is_ascii_alphabetic() || input == '~' {
+        let ansistr: String = self.ansi_buffer.drain(..).collect();
+        self.ansi(state, &ansistr);
+      }
+      return None;
+    }
+    if input == '\x1b' {
+      self.escaped = Some((self.mode.clone(), self.col));
+      self.ansi_buffer.push(input);
+      if self.mode == Mode::Insert {
+        self.col = self.col.saturating_sub(1);
+      }
+      self.mode = Mode::Normal;
+      self.pending = None;
+      self.render(state);
+      return None;
+    }
+    self.message.clear();
+    let app = match self.mode.clone() {
+      Mode::Normal => self.normal(state, input),
+      Mode::Insert => {
+        self.insert(input);
+        None
+      }
+      Mode::Command(prompt, line) => self.command(state, prompt, line, input),
+    };
+    if app.is_none() {
+      self.render(state);
+    }
+    app
+  }
+
+  fn scroll(&mut self, state: &mut TermState, lines_i32: i32) {
+    let lines = lines_i32.unsigned_abs() as usize;
+    self.row = if lines_i32 > 0 {
+      self.row.saturating_sub(lines)
+    } else {
+      self.row + lines
+    };
+    self.clamp();
+    self.render(state);
+  }
+
+  fn autocomplete(&self, _state: &TermState) -> Vec<String> {
+    vec![":q".to_string()]
+  }
+
+  // an escape at the end of the input was the escape key, so <Esc>O still opens a line above
+  fn input_end(&mut self, _state: &mut TermState) {
+    if self.escaped.take().is_some() {
+      self.ansi_buffer.clear();
+    }
+  }
+
+  // render scrolls the cursor back into view for the new size
+  fn resize(&mut self, state: &mut TermState) {
+    self.render(state);
+  }
+}
+
+impl Vi {
+  pub fn new() -> Self {
+    Self {
+      name: String::new(),
+      resolved: String::new(),
+      readonly: false,
+      modified: false,
+      lines: vec![vec![]],
+      row: 0,
+      col: 0,
+      top: 0,
+      left: 0,
+      mode: Mode::Normal,
+      pending: None,
+      register: vec![],
+      search: None,
+      message: String::new(),
+      ansi_buffer: vec![],
+      escaped: None,
+    }
+  }
+
+  // site files are opened read-only, files created in this session can be written
+  pub fn edit(state: &mut TermState, path_str: &str) -> Option<Box<dyn App>> {
+    let mut vi = Vi::new();
+    if !path_str.is_empty() {
+      vi.name = path_str.to_string();
+      vi.resolved = utils::resolve_path(state.path.join(path_str));
+      info!("{}", vi.resolved);
+      match filesystem::get_file(&vi.resolved) {
+        Ok(file) if file.is_dir => {
+          write_error!(state, format!("read error: {} Is a directory", path_str));
+          return None;
+        }
+        Ok(file) => {
+          return pending::spawn(
+            state,
+            async move {
+              let content = file.load().await;
+              (file, content)
+            },
+            move |state, (file, content)| match content {
+              Ok(content) => {
+                vi.show(state, &file, &content);
+                Some(Box::new(vi))
+              }
+              Err(error) => {
+                write_error!(state, error);
+                None
+              }
+            },
+          );
+        }
+        Err(_) => vi.message = format!("\"{}\" [New]", path_str),
+      }
+    }
+    vi.render(state);
+    Some(Box::new(vi))
+  }
+
+  fn show(&mut self, state: &TermState, file: &Node, content: &str) {
+    let content = wrap::strip_ansi(content);
+    self.lines = content.lines().map(|line| line.chars().collect()).collect();
+    if self.lines.is_empty() {
+      self.lines.push(vec![]);
+    }
+    self.readonly = matches!(file, Node::Lower(_));
+    self.message = format!("\"{}\" {}L", self.name, self.lines.len());
+    self.render(state);
+  }
+
+  fn normal(&mut self, state: &mut TermState, input: char) -> Option<Box<dyn App>> {
+    if let Some(pending) = self.pending.take() {
+      match (pending, input) {
+        ('d', 'd') => self.delete_line(),
+        ('y', 'y') => {
+          self.register = vec![self.lines[self.row].clone()];
+          self.message = "1 line yanked".to_string();
+        }
+        ('g', 'g') => self.row = 0,
+        _ => {}
+      }
+      self.clamp();
+      return None;
+    }
+    let page = state.height / 2;
+    match input {
+      'h' | '\x7f' => self.col = self.col.saturating_sub(1),
+      'l' | ' ' => self.col += 1,
+      'j' | '\r' => self.row += 1,
+      'k' => self.row = self.row.saturating_sub(1),
+      '0' => self.col = 0,
+      '$' => self.col = usize::MAX,
+      '^' => self.col = self.first_non_blank(),
+      'w' => (self.row, self.col) = self.word_forward(),
+      'b' => (self.row, self.col) = self.word_backward(),
+      'e' => (self.row, self.col) = self.word_end(),
+      'G' => self.row = usize::MAX,
+      // ctrl-d / ctrl-u
+      '\x04' => self.row += page,
+      '\x15' => self.row = self.row.saturating_sub(page),
+      'g' | 'd' | 'y' => self.pending = Some(input),
+      'x' => {
+        if self.col < self.lines[self.row].len() {
+          self.lines[self.row].remove(self.col);
+          self.modified = true;
+        }
+      }
+      'p' => self.put(self.row + 1),
+      'P' => self.put(self.row),
+      'i' => self.mode = Mode::Insert,
+      'a' => {
+        self.mode = Mode::Insert;
+        self.col = (self.col + 1).min(self.lines[self.row].len());
+      }
+      'I' => {
+        self.mode = Mode::Insert;
+        self.col = self.first_non_blank();
+      }
+      'A' => {
+        self.mode = Mode::Insert;
+        self.col = self.lines[self.row].len();
+      }
+      'o' | 'O' => {
+        if input == 'o' {
+          self.row += 1;
+        }
+        self.lines.insert(self.row, vec![]);
+        self.col = 0;
+        self.modified = true;
+        self.mode = Mode::Insert;
+      }
+      'n' => self.search_next(true),
+      'N' => self.search_next(false),
+      ':' | '/' => self.mode = Mode::Command(input, String::new()),
+      _ => {}
+    }
+    self.clamp();
+    None
+  }
+
+  fn insert(&mut self, input: char) {
+    let line = &mut self.lines[self.row];
+    match input {
+      '\r' => {
+        let rest = line.split_off(self.col);
+        self.row += 1;
+        self.col = 0;
+        self.lines.insert(self.row, rest);
+      }
+      '\x7f' | '\x08' => {
+        if self.col > 0 {
+          self.col -= 1;
+          line.remove(self.col);
+        } else if self.row > 0 {
+          let rest = self.lines.remove(self.row);
+          self.row -= 1;
+          self.col = self.lines[self.row].len();
+          self.lines[self.row].extend(rest);
+        } else {
+          return;
+        }
+      }
+      _ if input == '\t' || !input.is_control() => {
+        line.insert(self.col, input);
+        self.col += 1;
+      }
+      _ => return,
+    }
+    self.modified = true;
+  }
+
+  fn command(
+    &mut self,
+    state: &mut TermState,
+    prompt: char,
+    mut line: String,
+    input: char,
+  ) -> Option<Box<dyn App>> {
+    match input {
+      '\r' => {
+        self.mode = Mode::Normal;
+        if prompt == '/' {
+          if !line.is_empty() {
+            self.search = Some(line.chars().collect());
+          }
+          self.search_next(true);
+          return None;
+        }
+        return self.execute(state, line.trim());
+      }
+      '\x7f' | '\x08' => {
+        if line.pop().is_none() {
+          self.mode = Mode::Normal;
+          return None;
+        }
+      }
+      _ if !input.is_control() => line.push(input),
+      _ => {}
+    }
+    self.mode = Mode::Command(prompt, line);
+    None
+  }
+
+  fn execute(&mut self, state: &mut TermState, cmd: &str) -> Option<Box<dyn App>> {
+    let (cmd, arg) = match cmd.split_once(' ') {
+      Some((cmd, arg)) => (cmd, arg.trim()),
+      None => (cmd, ""),
+    };
+    if let Ok(line) = cmd.parse::<usize>() {
+      self.row = line.saturating_sub(1);
+      self.clamp();
+      return None;
+    }
+    let result = match cmd {
+      "w" => self.write(state, arg).map(|_| false),
+      "wq" | "x" => self.write(state, arg).map(|_| true),
+      "q" if self.modified => Err("E37: No write since last change (add ! to override)".to_string()),
+      "q" | "q!" => Ok(true),
+      _ => Err(format!("E492: Not an editor command: {}", cmd)),
+    };
+    match result {
+      Ok(true) => {
+        write!("{}", cursor_to(state.height, 1));
+        Shell::clear(state);
+        Some(Box::new(Shell::new()))
+      }
+      Ok(false) => None,
+      Err(error) => {
+        self.message = error;
+        None
+      }
+    }
+  }
+
+  fn write(&mut self, state: &TermState, arg: &str) -> Result<(), String> {
+    let (name, resolved) = if !arg.is_empty() {
+      (arg.to_string(), utils::resolve_path(state.path.join(arg)))
+    } else if self.name.is_empty() {
+      return Err("E32: No file name".to_string());
+    } else if self.readonly {
+      return Err("E45: 'readonly' option is set".to_string());
+    } else {
+      (self.name.clone(), self.resolved.clone())
+    };
+    let content: String = self
+      .lines
+      .iter()
+      .map(|line| line.iter().collect::<String>() + "\n")
+      .collect();
+    overlay::write(&resolved, &content, false).map_err(|error| format!("\"{}\" {}", name, error))?;
+    self.message = format!("\"{}\" {}L, {}B written", name, self.lines.len(), content.len());
+    if arg.is_empty() || self.name.is_empty() {
+      self.modified = false;
+    }
+    if self.name.is_empty() {
+      self.name = name;
+      self.resolved = resolved;
+    }
+    Ok(())
+  }
+
+  fn delete_line(&mut self) {
+    self.register = vec![self.lines.remove(self.row)];
+    if self.lines.is_empty() {
+      self.lines.push(vec![]);
+    }
+    self.modified = true;
+  }
+
+  fn put(&mut self, row: usize) {
+    if self.register.is_empty() {
+      return;
+    }
+    for (offset, line) in self.register.iter().enumerate() {
+      self.lines.insert(row + offset, line.clone());
+    }
+    self.row = row;
+    self.col = self.first_non_blank();
+    self.modified = true;
+  }
+
+  fn search_next(&mut self, forward: bool) {
+    let Some(pattern) = self.search.clone() else {
+      self.message = "E35: No previous regular expression".to_string();
+      return;
+    };
+    let matches: Vec<(usize, usize)> = self
+      .lines
+      .iter()
+      .enumerate()
+      .flat_map(|(row, line)| {
+        (0..line.len())
+          .filter(|col| line[*col..].starts_with(&pattern))
+          .map(move |col| (row, col))
+      })
+      .collect();
+    let cursor = (self.row, self.col);
+    let found = if forward {
+      matches.iter().find(|pos| **pos > cursor).or(matches.first())
+    } else {
+      matches.iter().rev().find(|pos| **pos < cursor).or(matches.last())
+    };
+    match found {
+      Some(&(row, col)) => {
+        if forward && (row, col) <= cursor {
+          self.message = "search hit BOTTOM, continuing at TOP".to_string();
+        } else if !forward && (row, col) >= cursor {
+          self.message = "search hit TOP, continuing at BOTTOM".to_string();
+        }
+        (self.row, self.col) = (row, col);
+      }
+      None => {
+        self.message = format!("E486: Pattern not found: {}", pattern.iter().collect::<String>())
+      }
+    }
+  }
+
+  fn first_non_blank(&self) -> usize {
+    let line = &self.lines[self.row];
+    line.iter().position(|c| !c.is_whitespace()).unwrap_or(line.len())
+  }
+
+  fn class_at(&self, (row, col): (usize, usize)) -> CharClass {
+    let line = &self.lines[row];
+    match line.get(col) {
+      _ if line.is_empty() => CharClass::Empty,
+      None => CharClass::Whitespace,
+      Some(c) if c.is_whitespace() => CharClass::Whitespace,
+      Some(c) if c.is_alphanumeric() || *c == '_' => CharClass::Word,
+      Some(_) => CharClass::Punctuation,
+    }
+  }
+
+  fn next_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+    if col + 1 < self.lines[row].len() {
+      Some((row, col + 1))
+    } else if row + 1 < self.lines.len() {
+      Some((row + 1, 0))
+    } else {
+      None
+    }
+  }
+
+  fn prev_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+    if col > 0 {
+      Some((row, col - 1))
+    } else if row > 0 {
+      Some((row - 1, self.lines[row - 1].len().saturating_sub(1)))
+    } else {
+      None
+    }
+  }
+
+  fn word_forward(&self) -> (usize, usize) {
+    let mut pos = (self.row, self.col);
+    let class = self.class_at(pos);
+    // skip the rest of the current word
+    loop {
+      let Some(next) = self.next_pos(pos) else {
+        return pos;
+      };
+      let line_break = next.0 != pos.0;
+      pos = next;
+      if line_break || self.class_at(pos) != class {
+        break;
+      }
+    }
+    while self.class_at(pos) == CharClass::Whitespace {
+      match self.next_pos(pos) {
+        Some(next) => pos = next,
+        None => break,
+      }
+    }
+    pos
+  }
+
+  fn word_backward(&self) -> (usize, usize) {
+    let Some(mut pos) = self.prev_pos((self.row, self.col)) else {
+      return (self.row, self.col);
+    };
+    while self.class_at(pos) == CharClass::Whitespace {
+      match self.prev_pos(pos) {
+        Some(prev) => pos = prev,
+        None => return pos,
+      }
+    }
+    let class = self.class_at(pos);
+    while let Some(prev) = self.prev_pos(pos) {
+      if prev.0 != pos.0 || self.class_at(prev) != class {
+        break;
+      }
+      pos = prev;
+    }
+    pos
+  }
+
+  fn word_end(&self) -> (usize, usize) {
+    let Some(mut pos) = self.next_pos((self.row, self.col)) else {
+      return (self.row, self.col);
+    };
+    while matches!(self.class_at(pos), CharClass::Whitespace | CharClass::Empty) {
+      match self.next_pos(pos) {
+        Some(next) => pos = next,
+        None => return pos,
+      }
+    }
+    let class = self.class_at(pos);
+    while let Some(next) = self.next_pos(pos) {
+      if next.0 != pos.0 || self.class_at(next) != class {
+        break;
+      }
+      pos = next;
+    }
+    pos
+  }
+
+  // keep the cursor on an existing character, insert mode may sit behind the last one
+  fn clamp(&mut self) {
+    self.row = self.row.min(self.lines.len() - 1);
+    let len = self.lines[self.row].len();
+    let max_col = if self.mode == Mode::Insert {
+      len
+    } else {
+      len.saturating_sub(1)
+    };
+    self.col = self.col.min(max_col);
+  }
+
+  fn render(&mut self, state: &TermState) {
+    let text_height = state.height.saturating_sub(1).max(1);
+    let width = state.width.max(1);
+    if self.row < self.top {
+      self.top = self.row;
+    } else if self.row >= self.top + text_height {
+      self.top = self.row + 1 - text_height;
+    }
+    let cursor_col = display_col(&self.lines[self.row], self.col);
+    if cursor_col < self.left {
+      self.left = cursor_col;
+    } else if cursor_col >= self.left + width {
+      self.left = cursor_col + 1 - width;
+    }
+
+    let mut out = String::new();
+    for screen_row in 0..text_height {
+      out += &cursor_to(screen_row + 1, 1);
+      out += consts::ERASE_LINE;
+      match self.lines.get(self.top + screen_row) {
+        Some(line) => {
+          out.extend(expand_tabs(line).into_iter().skip(self.left).take(width));
+        }
+        None => out += &Colour::Blue.bold().paint("~").to_string(),
+      }
+    }
+    out += &cursor_to(state.height, 1);
+    out += consts::ERASE_LINE;
+    out += &self.status_line(width);
+    let (cursor_row, cursor_col) = match &self.mode {
+      Mode::Command(_prompt, line) => (state.height, line.chars().count() + 2),
+      _ => (self.row - self.top + 1, cursor_col - self.left + 1),
+    };
+    out += &cursor_to(cursor_row, cursor_col);
+    write!("{}", out);
+  }
+
+  fn status_line(&self, width: usize) -> String {
+    if let Mode::Command(prompt, line) = &self.mode {
+      return format!("{}{}", prompt, line);
+    }
+    let status = if !self.message.is_empty() {
+      self.message.clone()
+    } else if self.mode == Mode::Insert {
+      Style::new().bold().paint("-- INSERT --").to_string()
+    } else {
+      let name = if self.name.is_empty() {
+        "[No Name]"
+      } else {
+        &self.name
+      };
+      format!(
+        "\"{}\"{}{}",
+        name,
+        if self.readonly { " [readonly]" } else { "" },
+        if self.modified { " [+]" } else { "" }
+      )
+    };
+    let position = format!("{},{}", self.row + 1, self.col + 1);
+    let status_len = wrap::strip_ansi(&status).chars().count();
+    let padding = width.saturating_sub(status_len + position.len() + 1);
+    format!("{}{} {}", status, " ".repeat(padding), position)
+  }
+
+  fn ansi(&mut self, state: &mut TermState, ansistr: &str) {
+    let page = state.height.saturating_sub(1);
+    match ansistr {
+      consts::UP => self.row = self.row.saturating_sub(1),
+      consts::DOWN => self.row += 1,
+      consts::LEFT => self.col = self.col.saturating_sub(1),
+      consts::RIGHT => self.col += 1,
+      consts::PAGE_UP => self.row = self.row.saturating_sub(page),
+      consts::PAGE_DOWN => self.row += page,
+      consts::PAGE_START => self.col = 0,
+      consts::PAGE_END => self.col = usize::MAX,
+      _ => return,
+    }
+    self.clamp();
+    self.render(state);
+  }
+}
+
+fn cursor_to(row: usize, col: usize) -> String {
+  format!("\x1b[{};{}H", row, col)
+}
+
+fn expand_tabs(line: &[char]) -> Vec<char> {
+  let mut expanded = vec![];
+  for c in line {
+    if *c == '\t' {
+      let spaces = TAB_WIDTH - expanded.len() % TAB_WIDTH;
+      expanded.extend(std::iter::repeat_n(' ', spaces));
+    } else {
+      expanded.push(*c);
+    }
+  }
+  expanded
+}
+
+fn display_col(line: &[char], col: usize) -> usize {
+  expand_tabs(&line[..col.min(line.len())]).len()
+}
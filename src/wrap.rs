@@ -0,0 +1,213 @@
+// width and wrapping of text with escape sequences, shared with build.rs, which includes this file
+// markdown is rendered at a fixed width there, less reflows it to the terminal width
+
+// drop escape sequences (colors, OSC links) and keep the plain text
+pub fn strip_ansi(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '\x1b' {
+      plain.push(c);
+      continue;
+    }
+    match chars.next() {
+      // CSI, ends with a byte in @..~
+      Some('[') => {
+        for c in chars.by_ref() {
+          if ('@'..='~').contains(&c) {
+            break;
+          }
+        }
+      }
+      // OSC, ends with BEL or ESC \
+      Some(']') => {
+        while let Some(c) = chars.next() {
+          if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+            break;
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+  plain
+}
+
+// byte length of the escape sequence `text` starts with, 0 if it doesn't start with one
+pub fn escape_len(text: &str) -> usize {
+  let mut chars = text.char_indices().skip(1);
+  if !text.starts_with('\x1b') {
+    return 0;
+  }
+  match chars.next() {
+    // CSI, ends with a byte in @..~
+    Some((_, '[')) => chars
+      .find(|(_, c)| ('@'..='~').contains(c))
+      .map_or(text.len(), |(offset, c)| offset + c.len_utf8()),
+    // OSC, ends with BEL or ESC \
+    Some((_, ']')) => {
+      for (offset, c) in chars {
+        if c == '\x07' {
+          return offset + 1;
+        }
+        if c == '\x1b' && text[offset + 1..].starts_with('\\') {
+          return offset + 2;
+        }
+      }
+      text.len()
+    }
+    Some((offset, c)) => offset + c.len_utf8(),
+    None => 1,
+  }
+}
+
+// columns the text takes up in the terminal
+pub fn width(text: &str) -> usize {
+  strip_ansi(text).chars().count()
+}
+
+// styled text wrapped at `width` columns, lines after the first start with `indent`
+// styles and OSC 8 links that are open at a break are closed before it and reopened after the indent
+pub fn wrap(text: &str, width: usize, indent: &str) -> Vec<String> {
+  let indent_width = self::width(indent);
+  let mut lines = vec![];
+  let mut styles: Vec<&str> = vec![];
+  let mut link: Option<&str> = None;
+  for paragraph in text.split('\n') {
+    let mut line = String::new();
+    let mut line_width = 0;
+    let mut empty = true;
+    for mut word in paragraph.split(' ') {
+      let mut space = !empty;
+      loop {
+        let word_width = self::width(word);
+        let available = width.saturating_sub(line_width + usize::from(space));
+        // spaces at a break are dropped, e.g. the padding at the end of a line
+        if word_width <= available || word_width == 0 {
+          if space && word_width <= available {
+            line.push(' ');
+            line_width += 1;
+          }
+          line.push_str(word);
+          line_width += word_width;
+          track(word, &mut styles, &mut link);
+          empty = false;
+          break;
+        }
+        // words longer than a whole line fill up the current one
+        if empty || word_width > width.saturating_sub(indent_width) {
+          let (head, tail) = split_at_width(word, if empty { available.max(1) } else { available });
+          if space && !head.is_empty() {
+            line.push(' ');
+          }
+          line.push_str(head);
+          track(head, &mut styles, &mut link);
+          word = tail;
+        }
+        if link.is_some() {
+          line.push_str("\x1b]8;;\x1b\\");
+        }
+        if !styles.is_empty() {
+          line.push_str("\x1b[0m");
+        }
+        lines.push(line);
+        line = format!("{}{}{}", indent, link.unwrap_or_default(), styles.concat());
+        line_width = indent_width;
+        empty = true;
+        space = false;
+      }
+    }
+    lines.push(line);
+  }
+  lines
+}
+
+// the part of a word that fits into `width` columns and the rest, escape sequences stay whole
+fn split_at_width(word: &str, width: usize) -> (&str, &str) {
+  let mut offset = 0;
+  let mut columns = 0;
+  while offset < word.len() {
+    let escape = escape_len(&word[offset..]);
+    if escape > 0 {
+      offset += escape;
+      continue;
+    }
+    if columns == width {
+      break;
+    }
+    offset += word[offset..].chars().next().map_or(1, char::len_utf8);
+    columns += 1;
+  }
+  word.split_at(offset)
+}
+
+// SGR styles and the OSC 8 link that are still open after a word
+fn track<'a>(word: &'a str, styles: &mut Vec<&'a str>, link: &mut Option<&'a str>) {
+  let mut offset = 0;
+  while let Some(start) = word[offset..].find('\x1b') {
+    let start = offset + start;
+    let sequence = &word[start..start + escape_len(&word[start..]).max(1)];
+    if sequence.starts_with("\x1b]8;") {
+      // an empty uri ends the link
+      let uri = sequence.trim_end_matches('\x07').trim_end_matches("\x1b\\").splitn(3, ';').nth(2);
+      *link = uri.is_some_and(|uri| !uri.is_empty()).then_some(sequence);
+    } else if sequence.starts_with("\x1b[") && sequence.ends_with('m') {
+      if sequence == "\x1b[0m" || sequence == "\x1b[m" {
+        styles.clear();
+      } else {
+        styles.push(sequence);
+      }
+    }
+    offset = start + sequence.len();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn wrap_breaks_at_spaces() {
+    assert_eq!(wrap("the quick brown fox", 10, ""), ["the quick", "brown fox"]);
+    assert_eq!(wrap("one two", 20, ""), ["one two"]);
+  }
+
+  #[test]
+  fn wrap_indents_continuation_lines() {
+    assert_eq!(wrap("• a list item that wraps", 12, "  "), ["• a list", "  item that", "  wraps"]);
+  }
+
+  #[test]
+  fn wrap_splits_long_words() {
+    assert_eq!(wrap("abcdefghij", 4, ""), ["abcd", "efgh", "ij"]);
+  }
+
+  #[test]
+  fn wrap_keeps_newlines() {
+    assert_eq!(wrap("a\nb", 10, ""), ["a", "b"]);
+  }
+
+  #[test]
+  fn wrap_reopens_styles() {
+    assert_eq!(
+      wrap("\x1b[1mbold text\x1b[0m", 4, ""),
+      ["\x1b[1mbold\x1b[0m", "\x1b[1mtext\x1b[0m"]
+    );
+  }
+
+  #[test]
+  fn wrap_reopens_links() {
+    let link = "\x1b]8;;https://example.com\x1b\\";
+    let end = "\x1b]8;;\x1b\\";
+    assert_eq!(
+      wrap(&format!("{}one two{}", link, end), 3, ""),
+      [format!("{}one{}", link, end), format!("{}two{}", link, end)]
+    );
+  }
+
+  #[test]
+  fn width_skips_escapes() {
+    assert_eq!(width("\x1b[31mred\x1b[0m"), 3);
+    assert_eq!(width("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"), 4);
+  }
+}
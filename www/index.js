@@ -314,7 +314,7 @@ addEventListener("resize", () => {
     }
   }
 
-  wasm.init(term.rows, term.cols, window.location.pathname);
+  wasm.resize(term.rows, term.cols);
 });
 
 term.onData(function (data) {
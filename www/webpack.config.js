@@ -24,10 +24,16 @@ module.exports = {
           to: "files",
         },
         { from: "../root", to: "root" },
+        // terminal views of raw markdown posts, rendered by build.rs
+        { from: "../rendered", to: "root", force: true, noErrorOnMissing: true },
+        // directory listings of builds with SITE_MANIFESTS=1
+        { from: "../manifests", to: "root", globOptions: { dot: true }, noErrorOnMissing: true },
         { from: "index.html", to: "404.html" },
         { from: "../dirs", to: "." },
         { from: "img", to: "img" },
         { from: "sitemap.xml", to: "sitemap.xml" },
+        { from: "feed.xml", to: "feed.xml" },
+        { from: "rss.xml", to: "rss.xml" },
         { from: "robots.txt", to: "robots.txt" },
       ],
     }),